@@ -1,7 +1,6 @@
-
 //! Utility module to match mnemonic to opcode and command's argument type
 
-use isa::Opcode;
+use isa::{Opcode, USER_OPCODES};
 
 use crate::parser::errors::ParsingError;
 use crate::parser::token::TokenStream;
@@ -12,6 +11,24 @@ pub struct SourceCommandMetadata {
     pub argument_type: fn(&mut TokenStream) -> Result<Argument, ParsingError>,
 }
 
+const fn user_command(index: u8) -> SourceCommandMetadata {
+    SourceCommandMetadata {
+        opcode: Opcode::USER(index),
+        argument_type: Argument::parse_optional_address,
+    }
+}
+
+static USER_COMMANDS: [SourceCommandMetadata; USER_OPCODES as usize] = [
+    user_command(0),
+    user_command(1),
+    user_command(2),
+    user_command(3),
+    user_command(4),
+    user_command(5),
+    user_command(6),
+    user_command(7),
+];
+
 impl SourceCommandMetadata {
     pub fn get_metadata_by_opcode(
         opcode: &str,
@@ -95,8 +112,27 @@ impl SourceCommandMetadata {
             }),
             "HALT" => Ok(&SourceCommandMetadata {
                 opcode: Opcode::HALT,
+                // exit code
+                argument_type: Argument::parse_optional_immediate,
+            }),
+            "TAS" => Ok(&SourceCommandMetadata {
+                opcode: Opcode::TAS,
+                argument_type: Argument::parse_address,
+            }),
+            "COREID" => Ok(&SourceCommandMetadata {
+                opcode: Opcode::COREID,
                 argument_type: Argument::parse_none,
             }),
+            "WCS" => Ok(&SourceCommandMetadata {
+                opcode: Opcode::WCS,
+                argument_type: Argument::parse_immediate,
+            }),
+            // USER0 .. USER7
+            user if user.starts_with("USER") => user["USER".len()..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| USER_COMMANDS.get(index))
+                .ok_or(ParsingError::UnknownCommand(opcode.to_owned())),
             _ => Err(ParsingError::UnknownCommand(opcode.to_owned())),
         }
     }
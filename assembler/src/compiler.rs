@@ -4,10 +4,11 @@
 //! Resolves references to labels. Generates sections from
 //! assembler directives
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use isa::CompiledProgram;
 use isa::CompiledSection;
+use isa::DebugInfo;
 use isa::MemoryItem;
 use isa::RawAddress;
 
@@ -55,7 +56,7 @@ impl<'a, T: Iterator<Item = &'a SourceCodeItem>> Iterator for AddressIterator<T>
         self.next_index_to_consume += 1;
 
         self.next_address = if let &SourceCodeItem::CompilerDirective(
-            CompilerDirective::SetAddress(address),
+            CompilerDirective::SetAddress(address, _),
         ) = item
         {
             // this directive is zero sized
@@ -72,11 +73,13 @@ impl<'a, T: Iterator<Item = &'a SourceCodeItem>> Iterator for AddressIterator<T>
 }
 
 impl ParsedProgram {
-    fn addresses(items: &[SourceCodeItem]) -> AddressIterator<std::slice::Iter<SourceCodeItem>> {
+    fn addresses(items: &[SourceCodeItem]) -> AddressIterator<std::slice::Iter<'_, SourceCodeItem>> {
         AddressIterator::new(items)
     }
 
-    pub fn compile(self) -> Result<CompiledProgram, CompilationError> {
+    /// Debug info maps labels to addresses they were resolved to
+    /// and instructions to their source lines
+    pub fn compile(self) -> Result<(CompiledProgram, DebugInfo), CompilationError> {
         // RESOLVE LABELS
 
         let mut labels: Vec<(Label, Index)> = self.labels.into_iter().collect();
@@ -100,17 +103,22 @@ impl ParsedProgram {
         // base section
         sections.push(CompiledSection::with_address(0));
 
+        let mut lines = BTreeMap::new();
+
         // create sections from ORG commands
         // distribute items among sections
-        for (current_address, item) in ParsedProgram::addresses(&self.items) {
+        let items = ParsedProgram::addresses(&self.items).zip(&self.lines);
+        for ((current_address, item), &line) in items {
             let current_section = sections
                 .last_mut()
                 .expect("At least default section must be present");
 
             match item {
                 SourceCodeItem::CompilerDirective(directive) => match directive {
-                    CompilerDirective::SetAddress(address) => {
-                        sections.push(CompiledSection::with_address(*address));
+                    CompilerDirective::SetAddress(address, kind) => {
+                        let mut section = CompiledSection::with_address(*address);
+                        section.kind = *kind;
+                        sections.push(section);
                         continue;
                     }
                     CompilerDirective::Data(data) => data
@@ -132,10 +140,17 @@ impl ParsedProgram {
                     let memory_item =
                         MemoryItem::Command(command.compile(&resolved_labels, current_address)?);
                     current_section.items.push(memory_item);
+                    lines.insert(current_address, line);
                 }
             };
         }
 
-        Ok(CompiledProgram { sections })
+        let debug_info = DebugInfo {
+            labels: resolved_labels.into_iter().collect(),
+            lines,
+            source: None,
+        };
+
+        Ok((CompiledProgram { sections }, debug_info))
     }
 }
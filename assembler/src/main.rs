@@ -1,8 +1,8 @@
-use cli_utils::{check_empty_arguments, ConfigurationError};
+use cli_utils::{check_empty_arguments, take_option, ConfigurationError};
 use std::{
     env,
     error::Error,
-    fs::{self, OpenOptions},
+    fs::{self, File, OpenOptions},
     path::PathBuf,
 };
 
@@ -23,31 +23,44 @@ fn main() {
 fn start() -> Result<(), Box<dyn Error>> {
     let config = parse_cli_args()?;
 
-    let input_string = fs::read_to_string(config.input_file)?;
+    let input_string = fs::read_to_string(&config.input_file)?;
 
     let parsed_program = parse_asm(input_string)?;
 
-    let compiled = parsed_program.compile()?;
+    let (compiled, mut debug_info) = parsed_program.compile()?;
+    debug_info.source = Some(config.input_file.display().to_string());
 
-    let output_file = OpenOptions::new()
+    if let Some(debug_info_file) = config.debug_info_file {
+        serde_json::to_writer(create_file(debug_info_file)?, &debug_info)?;
+    }
+
+    let output_file = create_file(config.output_file)?;
+
+    Ok(serde_json::to_writer(output_file, &compiled)?)
+}
+
+fn create_file(path: PathBuf) -> std::io::Result<File> {
+    OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
-        .open(config.output_file)?;
-
-    Ok(serde_json::to_writer(output_file, &compiled)?)
+        .open(path)
 }
 
 struct Config {
     input_file: PathBuf,
     output_file: PathBuf,
+    /// labels for debugger
+    debug_info_file: Option<PathBuf>,
 }
 
 /// accepts two positional args:
 /// input output
+/// and option --debug-info <path>
 fn parse_cli_args() -> Result<Config, ConfigurationError> {
     let mut args: Vec<String> = env::args().skip(1).collect();
     check_empty_arguments(&args)?;
+    let debug_info_file = take_option(&mut args, "--debug-info")?.map(PathBuf::from);
     args.reverse();
 
     let input_file: PathBuf = args
@@ -69,5 +82,6 @@ fn parse_cli_args() -> Result<Config, ConfigurationError> {
     Ok(Config {
         input_file,
         output_file,
+        debug_info_file,
     })
 }
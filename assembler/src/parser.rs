@@ -26,6 +26,8 @@ use self::token::TokenStream;
 pub struct ParsedProgram {
     pub labels: HashMap<Label, Index>,
     pub items: Vec<SourceCodeItem>,
+    /// source line of every item
+    pub lines: Vec<usize>,
 }
 
 impl ParsedProgram {
@@ -63,6 +65,8 @@ pub fn parse_asm(input: impl AsRef<str>) -> Result<ParsedProgram, ParsingErrorOn
         let line_number = line_index + 1;
         process_line(line, &mut program)
             .map_err(|error| ParsingErrorOnLine { error, line_number })?;
+        // line holds at most one item
+        program.lines.resize(program.items.len(), line_number);
     }
 
     Ok(program)
@@ -36,4 +36,21 @@ impl Argument {
         Ok(Argument::Address(address))
     }
 
+    /// User defined commands decide on their own whether operand is needed
+    pub fn parse_optional_address(stream: &mut TokenStream) -> Result<Argument, ParsingError> {
+        if stream.next_end_of_input().is_ok() {
+            return Ok(Argument::None);
+        }
+
+        Self::parse_address(stream)
+    }
+
+    pub fn parse_optional_immediate(stream: &mut TokenStream) -> Result<Argument, ParsingError> {
+        if stream.next_end_of_input().is_ok() {
+            return Ok(Argument::None);
+        }
+
+        Self::parse_immediate(stream)
+    }
+
 }
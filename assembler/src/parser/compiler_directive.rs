@@ -1,3 +1,5 @@
+use isa::SectionKind;
+
 use crate::source_code::CompilerDirective;
 
 use super::{token::{Token, TokenStream}, ParsingError};
@@ -27,8 +29,17 @@ impl CompilerDirective {
                 "ORG" => {
                     stream.next_word()?;
                     let address = stream.next_number()?;
+                    let kind = match stream.next_word() {
+                        Ok(kind) => Some(SectionKind::parse(&kind).ok_or_else(|| {
+                            ParsingError::Other(format!("unknown section kind `{kind}`"))
+                        })?),
+                        Err(_) => None,
+                    };
+
+                    // ensure that no unparsed input left
+                    stream.next_end_of_input()?;
 
-                    return Ok(Some(Self::SetAddress(address)));
+                    return Ok(Some(Self::SetAddress(address, kind)));
                 }
                 _ => return Ok(None),
             };
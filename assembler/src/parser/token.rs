@@ -6,7 +6,7 @@ use regex::Regex;
 
 pub static NUMBER_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^(?P<prefix>0[xb])?(?P<number>[\dabcdef_]+)").unwrap());
-pub static WORD_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[_\w--[\d]]+").unwrap());
+pub static WORD_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[_\w--[\d]][_\w]*").unwrap());
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Token {
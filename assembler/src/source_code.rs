@@ -31,7 +31,7 @@ impl SourceCodeItem {
                     .len()
                     .try_into()
                     .expect("Too big data item! It won't fit into cpu's memory"),
-                CompilerDirective::SetAddress(_) => 0,
+                CompilerDirective::SetAddress(..) => 0,
                 CompilerDirective::Pointer(_) => 1,
             },
         }
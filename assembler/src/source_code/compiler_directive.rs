@@ -1,4 +1,4 @@
-use isa::RawAddress;
+use isa::{RawAddress, SectionKind};
 
 use super::Label;
 
@@ -6,6 +6,7 @@ use super::Label;
 pub enum CompilerDirective {
     Data(Vec<u32>),
     Pointer(Label),
-    SetAddress(RawAddress),
+    /// kind of section starting at address, if given
+    SetAddress(RawAddress, Option<SectionKind>),
 }
 
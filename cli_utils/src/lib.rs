@@ -10,12 +10,52 @@ pub fn check_empty_arguments(args: &[String]) -> Result<(), ConfigurationError>
     Ok(())
 }
 
+/// Removes flag from arguments. Returns whether flag was present
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let position = args.iter().position(|arg| arg == flag);
+    if let Some(index) = position {
+        args.remove(index);
+    }
+
+    position.is_some()
+}
+
+/// Removes option along with its value from arguments
+pub fn take_option(
+    args: &mut Vec<String>,
+    option: &str,
+) -> Result<Option<String>, ConfigurationError> {
+    let Some(index) = args.iter().position(|arg| arg == option) else {
+        return Ok(None);
+    };
+
+    if index + 1 >= args.len() {
+        return Err(ConfigurationError::ArgumentNotFound {
+            argument_name: format!("value for {option}"),
+        });
+    }
+
+    args.remove(index);
+    Ok(Some(args.remove(index)))
+}
+
 #[derive(Debug)]
 pub enum ConfigurationError {
     InvalidUnicode,
     NotAFile(PathBuf),
-    ArgumentNotFound { argument_name: String },
+    ArgumentNotFound {
+        argument_name: String,
+    },
     EmptyArgument(usize),
+    InvalidValue {
+        argument_name: String,
+        value: String,
+    },
+    /// both arguments were given, but they do not work together
+    IncompatibleArguments {
+        argument_name: String,
+        other: String,
+    },
 }
 
 impl Error for ConfigurationError {}
@@ -29,6 +69,18 @@ impl Display for ConfigurationError {
             ConfigurationError::EmptyArgument(index) => {
                 writeln!(f, "Argument at position {} is empty!", index + 1)
             }
+            ConfigurationError::InvalidValue {
+                argument_name,
+                value,
+            } => {
+                writeln!(f, "Invalid value for {argument_name}: {value}")
+            }
+            ConfigurationError::IncompatibleArguments {
+                argument_name,
+                other,
+            } => {
+                writeln!(f, "{argument_name} can not be used with {other}")
+            }
             ConfigurationError::InvalidUnicode => {
                 writeln!(f, "Only Unicode arguments are supported!")
             }
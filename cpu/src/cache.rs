@@ -0,0 +1,366 @@
+//! Timing model of caches between CPU and memory. Caches hold tags only:
+//! values are always read from and written to `Memory`, so cache
+//! changes how many ticks an access takes, never what it returns.
+
+use std::{error::Error, fmt::Display};
+
+use isa::RawAddress;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// evict line which was not accessed for the longest time
+    Lru,
+    /// evict line which was loaded first
+    Fifo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// every write goes to memory. Write miss does not load line
+    WriteThrough,
+    /// writes stay in cache until dirty line is evicted. Write miss loads line
+    WriteBack,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// total amount of lines
+    pub lines: usize,
+    /// memory cells per line
+    pub line_size: usize,
+    /// lines per set. 1 is direct mapped, `lines` is fully associative
+    pub ways: usize,
+    pub replacement: Replacement,
+    pub write_policy: WritePolicy,
+    /// ticks to transfer a line or a word between cache and memory
+    pub miss_penalty: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            lines: 64,
+            line_size: 4,
+            ways: 1,
+            replacement: Replacement::Lru,
+            write_policy: WritePolicy::WriteBack,
+            miss_penalty: 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheConfigError {
+    /// lines, line size and ways have to be powers of two, ways may not exceed lines
+    InvalidGeometry {
+        lines: usize,
+        line_size: usize,
+        ways: usize,
+    },
+    UnknownKey {
+        key: String,
+    },
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+}
+
+impl Error for CacheConfigError {}
+
+impl Display for CacheConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheConfigError::InvalidGeometry {
+                lines,
+                line_size,
+                ways,
+            } => writeln!(
+                f,
+                "Cache of {lines} lines of {line_size} cells with {ways} ways is not possible: all have to be powers of two and ways may not exceed lines"
+            ),
+            CacheConfigError::UnknownKey { key } => writeln!(f, "Unknown cache parameter `{key}`"),
+            CacheConfigError::InvalidValue { key, value } => {
+                writeln!(f, "Invalid value `{value}` of cache parameter `{key}`")
+            }
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Parses comma separated `key=value` pairs, missing keys keep defaults:
+    /// `lines=64,line=4,ways=2,replace=lru|fifo,write=back|through,penalty=10`
+    pub fn parse(spec: &str) -> Result<Self, CacheConfigError> {
+        let mut config = Self::default();
+
+        for pair in spec.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let invalid = || CacheConfigError::InvalidValue {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            };
+            let number = || value.parse::<usize>().map_err(|_| invalid());
+
+            match key {
+                "lines" => config.lines = number()?,
+                "line" => config.line_size = number()?,
+                "ways" => config.ways = number()?,
+                "penalty" => config.miss_penalty = number()?,
+                "replace" => {
+                    config.replacement = match value {
+                        "lru" => Replacement::Lru,
+                        "fifo" => Replacement::Fifo,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "write" => {
+                    config.write_policy = match value {
+                        "back" => WritePolicy::WriteBack,
+                        "through" => WritePolicy::WriteThrough,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => {
+                    return Err(CacheConfigError::UnknownKey {
+                        key: key.to_owned(),
+                    })
+                }
+            }
+        }
+
+        config.verify()?;
+
+        Ok(config)
+    }
+
+    pub fn verify(&self) -> Result<(), CacheConfigError> {
+        let Self {
+            lines,
+            line_size,
+            ways,
+            ..
+        } = *self;
+
+        if [lines, line_size, ways].iter().all(|n| n.is_power_of_two()) && ways <= lines {
+            Ok(())
+        } else {
+            Err(CacheConfigError::InvalidGeometry {
+                lines,
+                line_size,
+                ways,
+            })
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CacheStats {
+    /// "unified", "instruction" or "data"
+    pub name: String,
+    pub reads: usize,
+    pub writes: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    /// dirty lines written back to memory
+    pub write_backs: usize,
+    /// ticks CPU waited for this cache
+    pub stall_ticks: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cache {}: reads {}, writes {}, hit rate {:.2}% ({} hits, {} misses), evictions {}, write backs {}, stall ticks {}",
+            self.name,
+            self.reads,
+            self.writes,
+            self.hit_rate() * 100.0,
+            self.hits,
+            self.misses,
+            self.evictions,
+            self.write_backs,
+            self.stall_ticks
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Line {
+    tag: usize,
+    dirty: bool,
+    /// access time for LRU, load time for FIFO
+    stamp: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    // accesses so far, orders lines for replacement
+    clock: usize,
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// Config is expected to be verified, see [`CacheConfig::verify`]
+    pub fn new(name: &str, config: CacheConfig) -> Self {
+        let sets = config.lines / config.ways;
+
+        Self {
+            config,
+            sets: vec![Vec::with_capacity(config.ways); sets],
+            clock: 0,
+            stats: CacheStats {
+                name: name.to_owned(),
+                ..CacheStats::default()
+            },
+        }
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Returns ticks access takes in addition to the tick which issued it.
+    /// Every transfer to or from memory takes `miss_penalty` and
+    /// wait states of memory, `latency`
+    pub fn access(&mut self, address: RawAddress, write: bool, latency: usize) -> usize {
+        let block = address as usize / self.config.line_size;
+        let set_count = self.sets.len();
+        let (set_index, tag) = (block % set_count, block / set_count);
+        let CacheConfig {
+            ways,
+            replacement,
+            write_policy,
+            miss_penalty,
+            ..
+        } = self.config;
+        let transfer = miss_penalty + latency;
+
+        self.clock += 1;
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+
+        let set = &mut self.sets[set_index];
+        let penalty = match set.iter_mut().find(|line| line.tag == tag) {
+            Some(line) => {
+                self.stats.hits += 1;
+                if replacement == Replacement::Lru {
+                    line.stamp = self.clock;
+                }
+
+                match (write, write_policy) {
+                    (true, WritePolicy::WriteThrough) => transfer,
+                    (true, WritePolicy::WriteBack) => {
+                        line.dirty = true;
+                        0
+                    }
+                    (false, _) => 0,
+                }
+            }
+            // write around: line is not loaded
+            None if write && write_policy == WritePolicy::WriteThrough => {
+                self.stats.misses += 1;
+                transfer
+            }
+            None => {
+                self.stats.misses += 1;
+
+                let mut penalty = transfer;
+                if set.len() == ways {
+                    let victim = set
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, line)| line.stamp)
+                        .map(|(index, _)| index)
+                        .unwrap_or_default();
+                    let evicted = set.swap_remove(victim);
+                    self.stats.evictions += 1;
+                    if evicted.dirty {
+                        self.stats.write_backs += 1;
+                        penalty += transfer;
+                    }
+                }
+
+                set.push(Line {
+                    tag,
+                    dirty: write,
+                    stamp: self.clock,
+                });
+
+                penalty
+            }
+        };
+
+        self.stats.stall_ticks += penalty;
+
+        penalty
+    }
+}
+
+/// Either one cache for instructions and data, or separate ones.
+/// Accesses which have no cache go straight to memory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum CacheHierarchy {
+    Unified(Cache),
+    Split {
+        instruction: Option<Cache>,
+        data: Option<Cache>,
+    },
+}
+
+impl CacheHierarchy {
+    pub fn unified(config: CacheConfig) -> Self {
+        CacheHierarchy::Unified(Cache::new("unified", config))
+    }
+
+    pub fn split(instruction: Option<CacheConfig>, data: Option<CacheConfig>) -> Self {
+        CacheHierarchy::Split {
+            instruction: instruction.map(|config| Cache::new("instruction", config)),
+            data: data.map(|config| Cache::new("data", config)),
+        }
+    }
+
+    /// Returns ticks CPU has to wait. Instruction fetch only reads.
+    /// `latency` is wait states of memory at `address`
+    pub fn access(
+        &mut self,
+        address: RawAddress,
+        write: bool,
+        instruction: bool,
+        latency: usize,
+    ) -> usize {
+        let cache = match self {
+            CacheHierarchy::Unified(cache) => Some(cache),
+            CacheHierarchy::Split {
+                instruction: cache, ..
+            } if instruction => cache.as_mut(),
+            CacheHierarchy::Split { data, .. } => data.as_mut(),
+        };
+
+        cache.map_or(latency, |cache| cache.access(address, write, latency))
+    }
+
+    pub fn stats(&self) -> Vec<CacheStats> {
+        match self {
+            CacheHierarchy::Unified(cache) => vec![cache.stats().clone()],
+            CacheHierarchy::Split { instruction, data } => [instruction, data]
+                .into_iter()
+                .flatten()
+                .map(|cache| cache.stats().clone())
+                .collect(),
+        }
+    }
+}
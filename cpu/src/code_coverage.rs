@@ -0,0 +1,368 @@
+//! Coverage of assembler source: how many times every instruction
+//! executed and which way every conditional jump went. Coverage of
+//! several runs of the same program merges into one. Lines come from
+//! debug info when report is made
+
+use std::{collections::BTreeMap, error::Error, fmt::Display, fmt::Write, io};
+
+use isa::{DebugInfo, MemoryItem, Opcode, RawAddress, MEMORY_SIZE};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cpu::{Cpu, ExecutedInstruction},
+    memory::Memory,
+    observer::{TickEvent, TickObserver},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CodeCoverage {
+    pub runs: usize,
+    /// hits of every instruction of program, executed or not
+    pub instructions: BTreeMap<RawAddress, u64>,
+    /// directions of every conditional jump
+    pub branches: BTreeMap<RawAddress, BranchCoverage>,
+}
+
+#[derive(Debug)]
+pub enum CodeCoverageError {
+    /// coverage was recorded for other program
+    ProgramMismatch,
+}
+
+impl Error for CodeCoverageError {}
+
+impl Display for CodeCoverageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodeCoverageError::ProgramMismatch => writeln!(
+                f,
+                "Coverage recorded for different programs can not be merged"
+            ),
+        }
+    }
+}
+
+/// Line of source with its instruction
+struct LineCoverage {
+    line: usize,
+    hits: u64,
+    branch: Option<BranchCoverage>,
+}
+
+impl CodeCoverage {
+    /// Coverage of single run of program burned into memory
+    pub fn new(memory: &Memory) -> Self {
+        let mut coverage = Self {
+            runs: 1,
+            ..Self::default()
+        };
+
+        for address in 0..MEMORY_SIZE {
+            let MemoryItem::Command(command) = memory[address] else {
+                continue;
+            };
+            let address = address as RawAddress;
+
+            coverage.instructions.insert(address, 0);
+            if is_conditional_jump(command.opcode) {
+                coverage.branches.insert(address, BranchCoverage::default());
+            }
+        }
+
+        coverage
+    }
+
+    pub fn merge(&mut self, other: &CodeCoverage) -> Result<(), CodeCoverageError> {
+        if self.instructions.is_empty() {
+            self.instructions = other
+                .instructions
+                .keys()
+                .map(|&address| (address, 0))
+                .collect();
+            self.branches = other
+                .branches
+                .keys()
+                .map(|&address| (address, BranchCoverage::default()))
+                .collect();
+        }
+
+        let same_program = self.instructions.keys().eq(other.instructions.keys())
+            && self.branches.keys().eq(other.branches.keys());
+        if !same_program {
+            return Err(CodeCoverageError::ProgramMismatch);
+        }
+
+        for (hits, other_hits) in self
+            .instructions
+            .values_mut()
+            .zip(other.instructions.values())
+        {
+            *hits += other_hits;
+        }
+        for (branch, other_branch) in self.branches.values_mut().zip(other.branches.values()) {
+            branch.taken += other_branch.taken;
+            branch.not_taken += other_branch.not_taken;
+        }
+
+        self.runs += other.runs;
+
+        Ok(())
+    }
+
+    /// Instructions which have source line, in order of lines
+    fn lines(&self, debug_info: &DebugInfo) -> Vec<LineCoverage> {
+        let mut lines: Vec<LineCoverage> = self
+            .instructions
+            .iter()
+            .filter_map(|(&address, &hits)| {
+                Some(LineCoverage {
+                    line: debug_info.line_of(address)?,
+                    hits,
+                    branch: self.branches.get(&address).copied(),
+                })
+            })
+            .collect();
+        lines.sort_by_key(|line| line.line);
+
+        lines
+    }
+
+    /// `source` overrides the one from debug info
+    pub fn to_text(&self, debug_info: &DebugInfo, source: Option<&str>) -> String {
+        let mut output = String::new();
+        let lines = self.lines(debug_info);
+        let (lines_hit, branches_hit, branches) = totals(&lines);
+
+        let _ = writeln!(
+            output,
+            "Code coverage of {} over {} run(s)",
+            source_name(debug_info, source),
+            self.runs
+        );
+        let _ = writeln!(output, "Lines: {lines_hit} of {} executed", lines.len());
+        let _ = writeln!(
+            output,
+            "Branches: {branches_hit} of {branches} directions taken"
+        );
+
+        let never_executed: Vec<String> = lines
+            .iter()
+            .filter(|line| line.hits == 0)
+            .map(|line| line.line.to_string())
+            .collect();
+        if !never_executed.is_empty() {
+            output.push('\n');
+            let _ = writeln!(
+                output,
+                "Never executed lines: {}",
+                never_executed.join(", ")
+            );
+        }
+
+        let partial: Vec<&LineCoverage> = lines
+            .iter()
+            .filter(|line| {
+                line.hits > 0
+                    && line
+                        .branch
+                        .is_some_and(|branch| branch.taken == 0 || branch.not_taken == 0)
+            })
+            .collect();
+        if !partial.is_empty() {
+            output.push('\n');
+            let _ = writeln!(output, "Jumps going one way only:");
+            for line in partial {
+                let branch = line.branch.unwrap_or_default();
+                let direction = if branch.taken == 0 {
+                    "never taken"
+                } else {
+                    "always taken"
+                };
+                let _ = writeln!(output, "{:>8} {direction}", line.line);
+            }
+        }
+
+        output
+    }
+
+    /// Tracefile of `lcov`, `genhtml` renders it.
+    /// Conditional jump has two branches: taken and not taken
+    pub fn to_lcov(&self, debug_info: &DebugInfo, source: Option<&str>) -> String {
+        let mut output = String::new();
+        let lines = self.lines(debug_info);
+        let (lines_hit, branches_hit, branches) = totals(&lines);
+
+        let _ = writeln!(output, "TN:");
+        let _ = writeln!(output, "SF:{}", source_name(debug_info, source));
+        for line in &lines {
+            let Some(branch) = line.branch else {
+                continue;
+            };
+            // `-` marks branch of line which never executed
+            let count = |count: u64| match line.hits {
+                0 => "-".to_owned(),
+                _ => count.to_string(),
+            };
+            let _ = writeln!(output, "BRDA:{},0,0,{}", line.line, count(branch.taken));
+            let _ = writeln!(output, "BRDA:{},0,1,{}", line.line, count(branch.not_taken));
+        }
+        let _ = writeln!(output, "BRF:{branches}");
+        let _ = writeln!(output, "BRH:{branches_hit}");
+        for line in &lines {
+            let _ = writeln!(output, "DA:{},{}", line.line, line.hits);
+        }
+        let _ = writeln!(output, "LF:{}", lines.len());
+        let _ = writeln!(output, "LH:{lines_hit}");
+        let _ = writeln!(output, "end_of_record");
+
+        output
+    }
+
+    /// Cobertura XML, as CI systems accept it
+    pub fn to_cobertura(&self, debug_info: &DebugInfo, source: Option<&str>) -> String {
+        let mut output = String::new();
+        let lines = self.lines(debug_info);
+        let (lines_hit, branches_hit, branches) = totals(&lines);
+        let rate = |hit: usize, all: usize| match all {
+            0 => 1.0,
+            all => hit as f64 / all as f64,
+        };
+        let line_rate = rate(lines_hit, lines.len());
+        let branch_rate = rate(branches_hit, branches);
+
+        let filename = source_name(debug_info, source);
+        let class = filename
+            .rsplit(['/', '\\'])
+            .next()
+            .and_then(|name| name.split('.').next())
+            .unwrap_or(filename);
+        let rates = format!("line-rate=\"{line_rate:.4}\" branch-rate=\"{branch_rate:.4}\"");
+
+        let _ = writeln!(output, r#"<?xml version="1.0" ?>"#);
+        let _ = writeln!(
+            output,
+            r#"<!DOCTYPE coverage SYSTEM "http://cobertura.sourceforge.net/xml/coverage-04.dtd">"#
+        );
+        let _ = writeln!(
+            output,
+            r#"<coverage {rates} lines-covered="{lines_hit}" lines-valid="{}" branches-covered="{branches_hit}" branches-valid="{branches}" complexity="0" version="{}" timestamp="0">"#,
+            lines.len(),
+            env!("CARGO_PKG_VERSION")
+        );
+        let _ = writeln!(output, "  <sources><source>.</source></sources>");
+        let _ = writeln!(output, "  <packages>");
+        let _ = writeln!(
+            output,
+            r#"    <package name="program" {rates} complexity="0">"#
+        );
+        let _ = writeln!(output, "      <classes>");
+        let _ = writeln!(
+            output,
+            r#"        <class name="{}" filename="{}" {rates} complexity="0">"#,
+            escape_xml(class),
+            escape_xml(filename)
+        );
+        let _ = writeln!(output, "          <methods/>");
+        let _ = writeln!(output, "          <lines>");
+        for line in &lines {
+            match line.branch {
+                None => {
+                    let _ = writeln!(
+                        output,
+                        r#"            <line number="{}" hits="{}" branch="false"/>"#,
+                        line.line, line.hits
+                    );
+                }
+                Some(branch) => {
+                    let taken = (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+                    let _ = writeln!(
+                        output,
+                        r#"            <line number="{}" hits="{}" branch="true" condition-coverage="{}% ({taken}/2)"/>"#,
+                        line.line,
+                        line.hits,
+                        taken * 50
+                    );
+                }
+            }
+        }
+        let _ = writeln!(output, "          </lines>");
+        let _ = writeln!(output, "        </class>");
+        let _ = writeln!(output, "      </classes>");
+        let _ = writeln!(output, "    </package>");
+        let _ = writeln!(output, "  </packages>");
+        let _ = writeln!(output, "</coverage>");
+
+        output
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl TickObserver for CodeCoverage {
+    fn observe(&mut self, event: &TickEvent, cpu: &Cpu) -> io::Result<()> {
+        let Some(instruction) = event.completed else {
+            return Ok(());
+        };
+
+        *self.instructions.entry(instruction.address).or_default() += 1;
+        if let Some(branch) = self.branches.get_mut(&instruction.address) {
+            // next instruction is about to be fetched
+            let next = instruction.address.wrapping_add(1);
+            if cpu.registers().program_counter == next {
+                branch.not_taken += 1;
+            } else {
+                branch.taken += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, last: Option<ExecutedInstruction>, _cpu: &Cpu) -> io::Result<()> {
+        if let Some(instruction) = last {
+            *self.instructions.entry(instruction.address).or_default() += 1;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_conditional_jump(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::JZC | Opcode::JZS | Opcode::JCC | Opcode::JCS
+    )
+}
+
+/// Lines hit, branch directions taken, all branch directions
+fn totals(lines: &[LineCoverage]) -> (usize, usize, usize) {
+    let lines_hit = lines.iter().filter(|line| line.hits > 0).count();
+    let branches = lines.iter().filter_map(|line| line.branch);
+    let branches_hit = branches
+        .clone()
+        .map(|branch| (branch.taken > 0) as usize + (branch.not_taken > 0) as usize)
+        .sum();
+
+    (lines_hit, branches_hit, branches.count() * 2)
+}
+
+fn source_name<'a>(debug_info: &'a DebugInfo, source: Option<&'a str>) -> &'a str {
+    source
+        .or(debug_info.source.as_deref())
+        .unwrap_or("program.asm")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
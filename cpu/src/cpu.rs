@@ -1,30 +1,106 @@
-use std::fmt::{Debug, Display};
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Display},
+    time::Instant,
+};
 
-use isa::{MemoryItem, Opcode, OperandType, RawAddress};
+use isa::{CompiledProgram, MemoryItem, Opcode, RawAddress, RawPort};
+use serde::{Deserialize, Serialize};
 
-use crate::{io_controller::IOController, memory::Memory};
+use crate::{
+    cache::CacheHierarchy,
+    io_controller::IOController,
+    latency::MemoryLatency,
+    memory::{Memory, MemoryError},
+    protection::{Access, MemoryProtection},
+};
 
-use self::{
-    control_unit::{Microinstruction, Signal},
-    data_path::{ALU_Config, Registers, ALU},
+pub use self::{
+    control_unit::{format_microinstruction, ControlUnit, Microinstruction, Signal},
+    data_path::Registers,
+    fault::{CpuFault, ExecutedInstruction, FaultCause},
+    history::{History, HistoryConfig, UndoneTick},
+    limits::ExecutionLimits,
+    report::{HaltReason, RunReport, ERROR_EXIT_CODE, MAX_EXIT_CODE},
+    snapshot::{Snapshot, SnapshotError},
     status::Status,
 };
+use self::{
+    coverage::MicrocodeCoverage,
+    data_path::{ALU_Config, ALU},
+    limits::LoopDetector,
+};
 
 mod control_unit;
+pub mod coverage;
 mod data_path;
+mod fault;
+mod hardwired;
+mod history;
+mod limits;
+pub mod pipeline;
+mod report;
+mod snapshot;
 mod status;
+pub mod tick_costs;
 
 type MicrocodeStorage = Vec<Microinstruction>;
 type MicroInstructionCounter = usize;
 type TRegisterValue = u32;
 
+/// Amount of instructions kept for fault report
+const RECENT_INSTRUCTIONS: usize = 8;
+const RESET_STATUS: Status = Status {
+    // all registers reset to zeroes
+    zero: true,
+    carry: false,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ExecutionCounters {
+    pub instructions: usize,
+    pub ticks: usize,
+    /// ticks spent waiting for memory, included into `ticks`
+    #[serde(default)]
+    pub stall_ticks: usize,
+}
+
+impl ExecutionCounters {
+    pub fn ticks_per_instruction(&self) -> f64 {
+        self.ticks as f64 / self.instructions.max(1) as f64
+    }
+}
+
 pub struct Cpu {
     io_controller: IOController,
     registers: Registers,
     status: Status,
     memory: Memory,
-    microcode: MicrocodeStorage,
-    microcode_program_counter: MicroInstructionCounter,
+    control_unit: ControlUnit,
+    instructions_executed: usize,
+    micro_instructions_executed: usize,
+    // dump state into log on every tick
+    log_ticks: bool,
+    microcode_coverage: Option<MicrocodeCoverage>,
+    halt_reason: Option<HaltReason>,
+    recent_instructions: VecDeque<ExecutedInstruction>,
+    limits: ExecutionLimits,
+    // set on first tick
+    started: Option<Instant>,
+    loop_detector: Option<LoopDetector>,
+    // set in multi-core system, tags tick log
+    core_id: Option<TRegisterValue>,
+    // atomic instruction is executing, other cores wait
+    bus_locked: bool,
+    caches: Option<CacheHierarchy>,
+    memory_latency: MemoryLatency,
+    memory_protection: Option<MemoryProtection>,
+    // ticks current microinstruction is held until memory is ready.
+    // None until its access is issued
+    memory_wait: Option<usize>,
+    // instruction fetch in progress: memory reads are instruction reads
+    fetching: bool,
+    stall_ticks: usize,
 }
 
 impl Debug for Cpu {
@@ -33,17 +109,20 @@ impl Debug for Cpu {
             .field("io_controller", &self.io_controller)
             .field("registers", &self.registers)
             .field("status", &self.status)
-            .field("microcode_program_counter", &self.microcode_program_counter)
+            .field("control_unit", &self.control_unit)
             .finish()
     }
 }
 
 impl Display for Cpu {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(core_id) = self.core_id {
+            writeln!(f, "Core: {core_id}")?;
+        }
         writeln!(f, "Registers:")?;
         writeln!(f, "{}", self.registers)?;
         writeln!(f, "Status: {}", self.status)?;
-        writeln!(f, "MC Counter: {}", self.microcode_program_counter)
+        write!(f, "{}", self.control_unit)
     }
 }
 
@@ -52,201 +131,606 @@ impl Cpu {
         Self {
             io_controller,
             registers: Registers::default(),
-            status: Status {
-                // all registers reset to zeroes
-                zero: true,
-                carry: false,
-            },
+            status: RESET_STATUS,
             memory,
-            microcode: control_unit::get_microcode(),
-            microcode_program_counter: 0,
+            control_unit: ControlUnit::microprogrammed(),
+            instructions_executed: 0,
+            micro_instructions_executed: 0,
+            log_ticks: true,
+            microcode_coverage: None,
+            halt_reason: None,
+            recent_instructions: VecDeque::with_capacity(RECENT_INSTRUCTIONS),
+            limits: ExecutionLimits::default(),
+            started: None,
+            loop_detector: None,
+            core_id: None,
+            bus_locked: false,
+            caches: None,
+            memory_latency: MemoryLatency::default(),
+            memory_protection: None,
+            memory_wait: None,
+            fetching: false,
+            stall_ticks: 0,
         }
     }
 
-    pub fn start(mut self) {
-        let mut instructions_executed = 0;
-        let mut micro_instructions_executed = 0;
-        loop {
-            // rise
-            let micro_instruction = self.microcode[self.microcode_program_counter].clone();
-            if micro_instruction.contains(&Signal::HALT) {
-                break;
-            }
+    pub fn without_tick_log(mut self) -> Self {
+        self.log_ticks = false;
 
-            let is_io = micro_instruction.contains(&Signal::IO);
-            let is_io_write = micro_instruction.contains(&Signal::WRITE_IO);
-            // lazy to defer access to data
-            // otherwise may access instruction by accident
-            let device_address = || u32::from(self.registers.data) as u8;
+        self
+    }
 
-            if is_io && is_io_write {
-                self.io_controller
-                    .write(device_address(), self.registers.accumulator as u8);
-            }
+    pub fn with_control_unit(mut self, control_unit: ControlUnit) -> Self {
+        self.control_unit = control_unit;
+        if self.microcode_coverage.is_some() {
+            self = self.with_microcode_coverage();
+        }
 
-            if micro_instruction.contains(&Signal::WRITE_MEM) {
-                self.memory[self.registers.address] = self.registers.data;
-            }
+        self
+    }
 
-            let left = if micro_instruction.contains(&Signal::ZERO_LEFT) {
-                0
-            } else if micro_instruction.contains(&Signal::SELECT_PC) {
-                // no sign extension happens
-                self.registers.program_counter as u32
-            } else {
-                self.registers.accumulator
-            };
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self.loop_detector = limits.detect_loops.then(LoopDetector::default);
 
-            let right_0 = micro_instruction.contains(&Signal::SELECT_RIGHT_DATA) as u8;
-            let right_1 =
-                (micro_instruction.contains(&Signal::SELECT_RIGHT_CMD_OPERAND) as u8) << 1;
-            let right = right_1 | right_0;
-            let right = match right {
-                0b00 => 0,
-                0b01 => self.registers.data.unwrap_data(),
-                0b10 => self.registers.command.operand.operand as u32,
-                0b11 => self.registers.address as u32,
-                _ => unreachable!(),
-            };
+        self
+    }
 
-            let alu_config = ALU_Config {
-                left,
-                right,
-                AND: micro_instruction.contains(&Signal::AND),
-                NOT_LEFT: micro_instruction.contains(&Signal::NOT_LEFT),
-                NOT_RIGHT: micro_instruction.contains(&Signal::NOT_RIGHT),
-                INC: micro_instruction.contains(&Signal::INC),
-                SHIFT: micro_instruction.contains(&Signal::SHIFT),
-                SHIFT_LEFT: micro_instruction.contains(&Signal::SHIFT_LEFT),
-            };
+    /// Memory accesses take extra ticks on cache miss
+    pub fn with_caches(mut self, caches: CacheHierarchy) -> Self {
+        self.caches = Some(caches);
 
-            let alu_output = ALU(alu_config);
+        self
+    }
 
-            // fall
-            if micro_instruction.contains(&Signal::WRITE_STATUS) {
-                self.status = Status {
-                    zero: alu_output.zero,
-                    carry: alu_output.carry,
-                };
-            }
+    pub fn caches(&self) -> Option<&CacheHierarchy> {
+        self.caches.as_ref()
+    }
 
-            if micro_instruction.contains(&Signal::WRITE_ACCUMULATOR) {
-                if is_io {
-                    // no sign extension happens
-                    self.registers.accumulator = self.io_controller.read(device_address()) as u32;
-                } else {
-                    self.registers.accumulator = alu_output.value;
-                }
+    /// Wait states of memory accesses
+    pub fn with_memory_latency(mut self, memory_latency: MemoryLatency) -> Self {
+        self.memory_latency = memory_latency;
+
+        self
+    }
+
+    /// Accesses which regions do not permit fault
+    pub fn with_memory_protection(mut self, memory_protection: MemoryProtection) -> Self {
+        self.memory_protection = Some(memory_protection);
+
+        self
+    }
+
+    pub fn memory_protection(&self) -> Option<&MemoryProtection> {
+        self.memory_protection.as_ref()
+    }
+
+    /// Memory ready signal: no access is in progress, so microinstruction
+    /// issued on next tick is not held back by previous one
+    pub fn memory_ready(&self) -> bool {
+        self.memory_wait.is_none_or(|wait| wait == 0)
+    }
+
+    /// Id returned by COREID. Tick log is tagged with it
+    pub fn with_core_id(mut self, core_id: TRegisterValue) -> Self {
+        self.core_id = Some(core_id);
+
+        self
+    }
+
+    pub fn core_id(&self) -> TRegisterValue {
+        self.core_id.unwrap_or_default()
+    }
+
+    /// Atomic instruction is in progress: no other core may access memory
+    /// until it completes
+    pub fn holds_bus(&self) -> bool {
+        self.bus_locked && self.halt_reason.is_none()
+    }
+
+    /// Memory changed behind CPU's back, e.g. by other core
+    pub(crate) fn forget_seen_states(&mut self) {
+        if let Some(loop_detector) = &mut self.loop_detector {
+            loop_detector.forget();
+        }
+    }
+
+    /// Count hits of control store addresses and signals
+    pub fn with_microcode_coverage(mut self) -> Self {
+        self.microcode_coverage = Some(MicrocodeCoverage::new(
+            self.control_unit.control_store_size(),
+        ));
+
+        self
+    }
+
+    pub fn microcode_coverage(&self) -> Option<&MicrocodeCoverage> {
+        self.microcode_coverage.as_ref()
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        self.status = status;
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn io_controller(&self) -> &IOController {
+        &self.io_controller
+    }
+
+    pub fn io_controller_mut(&mut self) -> &mut IOController {
+        &mut self.io_controller
+    }
+
+    pub fn control_unit(&self) -> &ControlUnit {
+        &self.control_unit
+    }
+
+    pub fn counters(&self) -> ExecutionCounters {
+        ExecutionCounters {
+            instructions: self.instructions_executed,
+            ticks: self.micro_instructions_executed,
+            stall_ticks: self.stall_ticks,
+        }
+    }
+
+    /// Why CPU stopped. `None` while it is able to continue
+    pub fn halt_reason(&self) -> Option<&HaltReason> {
+        self.halt_reason.as_ref()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt_reason.is_some()
+    }
+
+    /// Fetch of next instruction is in progress, its command
+    /// is not latched yet
+    pub fn is_fetching(&self) -> bool {
+        self.fetching || self.control_unit.is_fetching_instruction()
+    }
+
+    /// Instruction fetched last, along with its address
+    pub fn last_instruction(&self) -> Option<ExecutedInstruction> {
+        self.recent_instructions.back().copied()
+    }
+
+    /// Signals to be issued on next tick
+    pub fn current_microinstruction(&self) -> Microinstruction {
+        self.control_unit.microinstruction(&self.registers.command)
+    }
+
+    /// Architectural state is equal when registers and status are.
+    /// Memory is not compared: it changes only by WRITE_MEM, which
+    /// writes registers' content
+    pub fn architectural_state_eq(&self, other: &Cpu) -> bool {
+        self.registers == other.registers && self.status == other.status
+    }
+
+    pub fn start(mut self) -> RunReport {
+        self.run()
+    }
+
+    /// Same as `start`, but keeps CPU for inspection afterwards
+    pub fn run(&mut self) -> RunReport {
+        while self.tick() {}
+
+        self.finish()
+    }
+
+    /// Reset line: registers, status and control unit sequencer return
+    /// to power-on state, counters and device output are cleared.
+    /// Memory, devices and writable control store keep their content.
+    /// Microcode coverage keeps accumulating across runs
+    pub fn reset(&mut self) {
+        self.registers = Registers::default();
+        self.status = RESET_STATUS;
+        self.control_unit.reset();
+        self.io_controller.clear_written();
+        self.instructions_executed = 0;
+        self.micro_instructions_executed = 0;
+        self.stall_ticks = 0;
+        self.memory_wait = None;
+        self.fetching = false;
+        self.halt_reason = None;
+        self.recent_instructions.clear();
+        self.started = None;
+        self.bus_locked = false;
+        self.forget_seen_states();
+    }
+
+    /// Same as `reset`, but memory is replaced with freshly burned program
+    /// and control unit returns to power-on state, so writable control
+    /// store is empty. CPU is left untouched if program does not fit
+    /// into memory. Protection regions follow new program
+    pub fn reset_with_program(&mut self, program: CompiledProgram) -> Result<(), MemoryError> {
+        let memory_protection = self
+            .memory_protection
+            .as_ref()
+            .map(|protection| MemoryProtection::new(&program, protection.low_memory));
+        self.memory = Memory::burn(program)?;
+        self.memory_protection = memory_protection;
+        self.control_unit.power_on();
+        self.reset();
+
+        Ok(())
+    }
+
+    /// Logs final counters and reports on stopped CPU
+    pub fn finish(&self) -> RunReport {
+        log::info!(
+            "Instructions: {}; MC: {}",
+            self.instructions_executed,
+            self.micro_instructions_executed
+        );
+        if let Some(caches) = &self.caches {
+            for stats in caches.stats() {
+                log::info!("{stats}");
             }
+        }
+        if self.caches.is_some() || self.stall_ticks > 0 {
+            log::info!("Stall ticks: {}", self.stall_ticks);
+        }
+
+        self.report()
+    }
+
+    /// Report on current state. Halt reason must be known
+    pub fn report(&self) -> RunReport {
+        RunReport {
+            halt_reason: self
+                .halt_reason
+                .clone()
+                .expect("report is requested after CPU stopped"),
+            counters: self.counters(),
+            registers: self.registers.clone(),
+            status: self.status,
+            output: self.io_controller.written().to_vec(),
+            caches: self
+                .caches
+                .as_ref()
+                .map(CacheHierarchy::stats)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Executes single microinstruction. Returns `false` when CPU stops,
+    /// see `halt_reason` for why
+    pub fn tick(&mut self) -> bool {
+        if self.halt_reason.is_some() {
+            return false;
+        }
+
+        let started = *self.started.get_or_insert_with(Instant::now);
+        if let Some(reason) = self.limits.exceeded(self.counters(), started) {
+            self.halt_reason = Some(reason);
+            return false;
+        }
 
-            if micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER) {
-                self.registers.program_counter = alu_output.value as RawAddress;
+        // rise
+        let micro_instruction = self.current_microinstruction();
+        if self.control_unit.is_fetching_instruction() {
+            self.fetching = true;
+        }
+        if self.wait_for_memory(&micro_instruction) {
+            return true;
+        }
+
+        if let Some(coverage) = &mut self.microcode_coverage {
+            coverage.record(
+                self.control_unit.microcode_program_counter(),
+                &micro_instruction,
+            );
+        }
+
+        if micro_instruction.contains(&Signal::HALT) {
+            // operand is fetched into data register.
+            // Without operand data register still holds the command
+            let exit_code = match self.registers.data {
+                MemoryItem::Data(value) => value,
+                MemoryItem::Command(_) => 0,
+            };
+            if exit_code > MAX_EXIT_CODE {
+                let fault = self.fault(FaultCause::InvalidExitCode { exit_code });
+                log::error!("{fault}");
+                self.halt_reason = Some(HaltReason::Fault(fault));
+                return false;
             }
+            self.halt_reason = Some(HaltReason::Halt { exit_code });
+            return false;
+        }
 
-            let invert_flags = micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_CLEAR);
+        let is_io_read = micro_instruction.contains(&Signal::IO)
+            && micro_instruction.contains(&Signal::WRITE_ACCUMULATOR);
+        let device_address = self.registers.data.data().map(|port| port as RawPort);
+        if is_io_read && device_address.is_some_and(|port| self.io_controller.is_exhausted(port)) {
+            self.halt_reason = Some(HaltReason::InputExhausted);
+            return false;
+        }
 
-            // Z invert write
-            // 0 0      0
-            // 1 0      1
-            // 1 1      0
-            // 0 1      1
-            if micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_Z)
-                && self.status.zero != invert_flags
-            {
-                self.registers.program_counter = alu_output.value as RawAddress;
+        if let Err(cause) = self.execute(&micro_instruction) {
+            let fault = self.fault(cause);
+            log::error!("{fault}");
+            self.halt_reason = Some(HaltReason::Fault(fault));
+            return false;
+        }
+
+        if self.log_ticks {
+            log::info!("{}", self);
+        }
+
+        self.control_unit
+            .advance(&micro_instruction, &self.registers.command);
+
+        if self.control_unit.is_fetching_instruction() {
+            self.instructions_executed += 1;
+            self.bus_locked = false;
+        }
+
+        self.micro_instructions_executed += 1;
+
+        if let Some(loop_detector) = &mut self.loop_detector {
+            let changes_environment = [Signal::IO, Signal::WRITE_MEM, Signal::WRITE_CONTROL_STORE]
+                .iter()
+                .any(|signal| micro_instruction.contains(signal));
+            if changes_environment {
+                loop_detector.forget();
             }
 
-            if micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_C)
-                && self.status.carry != invert_flags
+            if self.control_unit.is_fetching_instruction()
+                && loop_detector.repeats(&self.registers, self.status)
             {
-                self.registers.program_counter = alu_output.value as RawAddress;
+                self.halt_reason = Some(HaltReason::InfiniteLoop {
+                    program_counter: self.registers.program_counter,
+                });
+                return false;
             }
+        }
 
-            if micro_instruction.contains(&Signal::WRITE_COMMAND) {
-                if let MemoryItem::Command(command) = self.registers.data {
-                    self.registers.command = command;
-                } else {
-                    panic!("Tried to write binary data into command register!")
-                }
-            }
+        true
+    }
 
-            let select_memory = micro_instruction.contains(&Signal::SELECT_MEM);
-            if micro_instruction.contains(&Signal::WRITE_DATA) {
-                self.registers.data = if select_memory {
-                    self.memory[self.registers.address]
-                } else {
-                    MemoryItem::Data(alu_output.value)
-                }
-            }
+    /// Holds microinstruction until memory is ready.
+    /// Returns `true` if tick was spent waiting
+    fn wait_for_memory(&mut self, micro_instruction: &Microinstruction) -> bool {
+        let wait = match self.memory_wait {
+            Some(wait) => wait,
+            None => self.memory_access_ticks(micro_instruction),
+        };
+        if wait == 0 {
+            self.memory_wait = None;
+            return false;
+        }
 
-            if micro_instruction.contains(&Signal::WRITE_ADDRESS) && !select_memory {
-                self.registers.address = alu_output.value as RawAddress;
+        self.memory_wait = Some(wait - 1);
+        self.micro_instructions_executed += 1;
+        self.stall_ticks += 1;
+        if self.log_ticks {
+            log::info!("Waiting for memory, {} ticks left", wait - 1);
+        }
+
+        true
+    }
+
+    /// Extra ticks memory accesses of microinstruction take
+    fn memory_access_ticks(&mut self, micro_instruction: &Microinstruction) -> usize {
+        let address = self.registers.address;
+        let latency = self.memory_latency.of(address);
+        let mut access = |write, latency| match &mut self.caches {
+            Some(caches) => caches.access(address, write, self.fetching && !write, latency),
+            None => latency,
+        };
+
+        let mut ticks = 0;
+        if micro_instruction.contains(&Signal::SELECT_MEM)
+            && micro_instruction.contains(&Signal::WRITE_DATA)
+        {
+            ticks += access(false, latency.read);
+        }
+        if micro_instruction.contains(&Signal::WRITE_MEM) {
+            ticks += access(true, latency.write);
+        }
+
+        ticks
+    }
+
+    fn fault(&self, cause: FaultCause) -> CpuFault {
+        CpuFault {
+            cause,
+            program_counter: self.registers.program_counter,
+            address: self.registers.address,
+            micro_instruction: self.control_unit.microcode_program_counter(),
+            tick: self.micro_instructions_executed,
+            recent_instructions: self.recent_instructions.iter().copied().collect(),
+        }
+    }
+
+    fn data_register(&self) -> Result<TRegisterValue, FaultCause> {
+        match self.registers.data {
+            MemoryItem::Data(value) => Ok(value),
+            MemoryItem::Command(command) => Err(FaultCause::InstructionAsData { command }),
+        }
+    }
+
+    /// Memory protection permits access to address register
+    fn check_access(&self, access: Access) -> Result<(), FaultCause> {
+        match &self.memory_protection {
+            Some(protection) => protection
+                .check(self.registers.address, access)
+                .map_err(FaultCause::Protection),
+            None => Ok(()),
+        }
+    }
+
+    /// Issues signals of microinstruction
+    fn execute(&mut self, micro_instruction: &Microinstruction) -> Result<(), FaultCause> {
+        if micro_instruction.contains(&Signal::WRITE_CONTROL_STORE) {
+            let slot = self.data_register()? as usize;
+            if let Err(error) = self
+                .control_unit
+                .write_control_store(slot, self.registers.accumulator)
+            {
+                log::warn!("Control store write rejected: {error}");
             }
+        }
 
-            log::info!("{}", self);
+        let is_io = micro_instruction.contains(&Signal::IO);
+        let is_io_write = micro_instruction.contains(&Signal::WRITE_IO);
+        // data register holds port only during IO
+        // otherwise may access instruction by accident
+        let device_address = if is_io {
+            self.data_register()? as RawPort
+        } else {
+            0
+        };
+
+        if is_io && is_io_write {
+            self.io_controller
+                .write(device_address, self.registers.accumulator as u8);
+        }
 
-            let mc_0 = micro_instruction.contains(&Signal::SELECT_MC_0) as u8;
-            let mc_1 = (micro_instruction.contains(&Signal::SELECT_MC_1) as u8) << 1;
+        if micro_instruction.contains(&Signal::WRITE_MEM) {
+            self.check_access(Access::Write)?;
+            self.memory[self.registers.address] = self.registers.data;
+        }
 
-            let mc = mc_0 | mc_1;
-            self.microcode_program_counter = match mc {
-                0b00 => self.microcode_program_counter + 1,
-                0b01 => 0,
-                0b10 => Self::operand_type_to_mc(self.registers.command.operand.operand_type),
-                0b11 => Self::opcode_to_mc(self.registers.command.opcode),
-                _ => unreachable!(),
+        let left = if micro_instruction.contains(&Signal::ZERO_LEFT) {
+            0
+        } else if micro_instruction.contains(&Signal::SELECT_PC) {
+            // no sign extension happens
+            self.registers.program_counter as u32
+        } else {
+            self.registers.accumulator
+        };
+
+        let right_0 = micro_instruction.contains(&Signal::SELECT_RIGHT_DATA) as u8;
+        let right_1 = (micro_instruction.contains(&Signal::SELECT_RIGHT_CMD_OPERAND) as u8) << 1;
+        let right = right_1 | right_0;
+        let right = match right {
+            _ if micro_instruction.contains(&Signal::SELECT_CORE_ID) => self.core_id(),
+            0b00 => 0,
+            0b01 => self.data_register()?,
+            0b10 => self.registers.command.operand.operand as u32,
+            0b11 => self.registers.address as u32,
+            _ => unreachable!(),
+        };
+
+        let alu_config = ALU_Config {
+            left,
+            right,
+            AND: micro_instruction.contains(&Signal::AND),
+            NOT_LEFT: micro_instruction.contains(&Signal::NOT_LEFT),
+            NOT_RIGHT: micro_instruction.contains(&Signal::NOT_RIGHT),
+            INC: micro_instruction.contains(&Signal::INC),
+            SHIFT: micro_instruction.contains(&Signal::SHIFT),
+            SHIFT_LEFT: micro_instruction.contains(&Signal::SHIFT_LEFT),
+        };
+
+        let alu_output = ALU(alu_config);
+
+        // fall
+        if micro_instruction.contains(&Signal::WRITE_STATUS) {
+            self.status = Status {
+                zero: alu_output.zero,
+                carry: alu_output.carry,
             };
+        }
 
-            if self.microcode_program_counter == 0 {
-                instructions_executed += 1;
+        if micro_instruction.contains(&Signal::WRITE_ACCUMULATOR) {
+            if is_io {
+                // no sign extension happens
+                self.registers.accumulator =
+                    self.io_controller
+                        .read(device_address)
+                        .ok_or(FaultCause::DeviceRead {
+                            port: device_address,
+                        })? as u32;
+            } else {
+                self.registers.accumulator = alu_output.value;
             }
+        }
 
-            micro_instructions_executed += 1;
+        if micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER) {
+            self.registers.program_counter = alu_output.value as RawAddress;
         }
 
-        log::info!(
-            "Instructions: {}; MC: {}",
-            instructions_executed,
-            micro_instructions_executed
-        );
-    }
+        let invert_flags = micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_CLEAR);
+
+        // Z invert write
+        // 0 0      0
+        // 1 0      1
+        // 1 1      0
+        // 0 1      1
+        if micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_Z)
+            && self.status.zero != invert_flags
+        {
+            self.registers.program_counter = alu_output.value as RawAddress;
+        }
 
-    fn opcode_to_mc(opcode: Opcode) -> MicroInstructionCounter {
-        // security mechanism is required here
-        // each table entry should have bitmask of allowed argument types
-        // if argument is not allowed, then processor should throw
-        // security exception
-        // after all, this is cratch too.
-        // proper way would be to introduce command formats.
-        // This is too complicated for the lab, so leaving it as is
-        match opcode {
-            Opcode::IN => 13,
-            Opcode::OUT => 14,
-            Opcode::LOAD => 15,
-            Opcode::STORE => 16,
-            Opcode::ADD => 18,
-            Opcode::INC => 19,
-            Opcode::AND => 20,
-            Opcode::CMP => 21,
-            Opcode::SHIFT_LEFT => 22,
-            Opcode::SHIFT_RIGHT => 23,
-            Opcode::JZC => 24,
-            Opcode::JZS => 25,
-            Opcode::JCC => 26,
-            Opcode::JCS => 27,
-            Opcode::JUMP => 28,
-            // just fetch next instruction
-            Opcode::NOP => 0,
-            Opcode::HALT => 29,
-        }
-    }
-    fn operand_type_to_mc(operand: OperandType) -> MicroInstructionCounter {
-        match operand {
-            OperandType::None => 3,
-            OperandType::Indirect => 9,
-            OperandType::Absolute => 5,
-            OperandType::Relative => 7,
-            OperandType::Immediate => 4,
+        if micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_C)
+            && self.status.carry != invert_flags
+        {
+            self.registers.program_counter = alu_output.value as RawAddress;
         }
+
+        if micro_instruction.contains(&Signal::WRITE_COMMAND) {
+            match self.registers.data {
+                MemoryItem::Command(command) if !command.opcode.is_valid() => {
+                    return Err(FaultCause::InvalidOpcode { command })
+                }
+                MemoryItem::Command(command) => {
+                    self.registers.command = command;
+                    self.bus_locked = command.opcode == Opcode::TAS;
+                    self.fetching = false;
+                    if self.recent_instructions.len() == RECENT_INSTRUCTIONS {
+                        self.recent_instructions.pop_front();
+                    }
+                    // address register still points to fetched cell
+                    self.recent_instructions.push_back(ExecutedInstruction {
+                        address: self.registers.address,
+                        command,
+                    });
+                }
+                MemoryItem::Data(value) => return Err(FaultCause::DataAsInstruction { value }),
+            }
+        }
+
+        let select_memory = micro_instruction.contains(&Signal::SELECT_MEM);
+        if micro_instruction.contains(&Signal::WRITE_DATA) {
+            self.registers.data = if select_memory {
+                let access = if self.fetching {
+                    Access::Execute
+                } else {
+                    Access::Read
+                };
+                self.check_access(access)?;
+                self.memory[self.registers.address]
+            } else {
+                MemoryItem::Data(alu_output.value)
+            }
+        }
+
+        if micro_instruction.contains(&Signal::WRITE_ADDRESS) && !select_memory {
+            self.registers.address = alu_output.value as RawAddress;
+        }
+
+        Ok(())
     }
 }
@@ -1,6 +1,14 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{Debug, Display},
+    ops::Range,
+};
 
-use super::MicrocodeStorage;
+use isa::{CompiledCommand, Opcode, OperandType, USER_OPCODES};
+use serde::{Deserialize, Serialize};
+
+use super::{hardwired::HardwiredControlUnit, MicroInstructionCounter, MicrocodeStorage};
 
 // this is how I want to call signals. I strongly believe that
 // signal names should be uppercase as they are constants to me
@@ -76,10 +84,558 @@ pub enum Signal {
 
     // processor control
     HALT,
+
+    // writable control store
+    // control_store[data] <- accumulator
+    WRITE_CONTROL_STORE,
+
+    // right alu input is id of the core,
+    // overrides RIGHT MULTIPLEXOR
+    SELECT_CORE_ID,
+}
+
+impl Signal {
+    /// Binary encoding of microinstruction. Signal's index is its bit number.
+    /// New signals must be appended to keep encoding stable
+    pub const ALL: [Signal; 28] = [
+        Signal::IO,
+        Signal::WRITE_IO,
+        Signal::SELECT_MEM,
+        Signal::WRITE_MEM,
+        Signal::WRITE_ACCUMULATOR,
+        Signal::WRITE_DATA,
+        Signal::WRITE_ADDRESS,
+        Signal::WRITE_STATUS,
+        Signal::WRITE_COMMAND,
+        Signal::WRITE_PROGRAM_COUNTER,
+        Signal::WRITE_PROGRAM_COUNTER_Z,
+        Signal::WRITE_PROGRAM_COUNTER_C,
+        Signal::WRITE_PROGRAM_COUNTER_CLEAR,
+        Signal::AND,
+        Signal::NOT_LEFT,
+        Signal::NOT_RIGHT,
+        Signal::INC,
+        Signal::SHIFT,
+        Signal::SHIFT_LEFT,
+        Signal::ZERO_LEFT,
+        Signal::SELECT_PC,
+        Signal::SELECT_RIGHT_CMD_OPERAND,
+        Signal::SELECT_RIGHT_DATA,
+        Signal::SELECT_MC_1,
+        Signal::SELECT_MC_0,
+        Signal::HALT,
+        Signal::WRITE_CONTROL_STORE,
+        Signal::SELECT_CORE_ID,
+    ];
 }
 
 pub type Microinstruction = HashSet<Signal>;
 
+/// Part of control unit which changes during execution
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ControlUnitState {
+    Microprogrammed {
+        microcode_program_counter: MicroInstructionCounter,
+        /// encoded microinstructions of writable control store
+        writable_control_store: Vec<u32>,
+    },
+    Hardwired(HardwiredControlUnit),
+}
+
+#[derive(Clone)]
+pub enum ControlUnit {
+    Microprogrammed {
+        microcode: MicrocodeStorage,
+        microcode_program_counter: MicroInstructionCounter,
+    },
+    Hardwired(HardwiredControlUnit),
+}
+
+impl Debug for ControlUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => f
+                .debug_struct("Microprogrammed")
+                .field("microcode_program_counter", microcode_program_counter)
+                .finish(),
+            ControlUnit::Hardwired(control_unit) => {
+                f.debug_tuple("Hardwired").field(control_unit).finish()
+            }
+        }
+    }
+}
+
+impl Display for ControlUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            &ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => match user_routine_position(microcode_program_counter) {
+                Some((routine, step)) => writeln!(
+                    f,
+                    "MC Counter: {microcode_program_counter} (USER{routine} step {step})"
+                ),
+                None => writeln!(f, "MC Counter: {microcode_program_counter}"),
+            },
+            ControlUnit::Hardwired(control_unit) => writeln!(f, "Hardwired: {control_unit}"),
+        }
+    }
+}
+
+impl ControlUnit {
+    pub fn microprogrammed() -> Self {
+        let microcode = get_microcode();
+        verify_microcode(&microcode).expect("built-in microcode should be valid");
+
+        Self::Microprogrammed {
+            microcode,
+            microcode_program_counter: 0,
+        }
+    }
+
+    pub fn hardwired() -> Self {
+        Self::Hardwired(HardwiredControlUnit::default())
+    }
+
+    /// Sequencer starts from instruction fetch.
+    /// Writable control store keeps its content
+    pub fn reset(&mut self) {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => *microcode_program_counter = 0,
+            ControlUnit::Hardwired(control_unit) => *control_unit = HardwiredControlUnit::default(),
+        }
+    }
+
+    /// Power-on state: sequencer starts from instruction fetch and
+    /// writable control store is empty again
+    pub fn power_on(&mut self) {
+        match self {
+            ControlUnit::Microprogrammed { .. } => *self = ControlUnit::microprogrammed(),
+            ControlUnit::Hardwired(_) => *self = ControlUnit::hardwired(),
+        }
+    }
+
+    /// Signals to be issued during current tick
+    pub fn microinstruction(&self, command: &CompiledCommand) -> Microinstruction {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode,
+                microcode_program_counter,
+            } => microcode[*microcode_program_counter].clone(),
+            ControlUnit::Hardwired(control_unit) => control_unit.signals(command),
+        }
+    }
+
+    pub fn advance(&mut self, micro_instruction: &Microinstruction, command: &CompiledCommand) {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => {
+                let mc_0 = micro_instruction.contains(&Signal::SELECT_MC_0) as u8;
+                let mc_1 = (micro_instruction.contains(&Signal::SELECT_MC_1) as u8) << 1;
+
+                let mc = mc_0 | mc_1;
+                *microcode_program_counter = match mc {
+                    0b00 => *microcode_program_counter + 1,
+                    0b01 => 0,
+                    0b10 => operand_type_to_mc(command.operand.operand_type),
+                    0b11 => opcode_to_mc(command.opcode),
+                    _ => unreachable!(),
+                };
+            }
+            ControlUnit::Hardwired(control_unit) => {
+                control_unit.advance(micro_instruction, command)
+            }
+        }
+    }
+
+    /// Address of next microinstruction. Hardwired control unit has none
+    pub fn microcode_program_counter(&self) -> Option<MicroInstructionCounter> {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => Some(*microcode_program_counter),
+            ControlUnit::Hardwired(_) => None,
+        }
+    }
+
+    /// Number of microinstructions in control store. Hardwired control unit has none
+    pub fn control_store_size(&self) -> usize {
+        match self {
+            ControlUnit::Microprogrammed { microcode, .. } => microcode.len(),
+            ControlUnit::Hardwired(_) => 0,
+        }
+    }
+
+    /// Whether next tick starts fetch of new instruction
+    pub fn is_fetching_instruction(&self) -> bool {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => *microcode_program_counter == 0,
+            ControlUnit::Hardwired(control_unit) => control_unit.is_fetching_instruction(),
+        }
+    }
+
+    pub fn state(&self) -> ControlUnitState {
+        match self {
+            ControlUnit::Microprogrammed {
+                microcode,
+                microcode_program_counter,
+            } => ControlUnitState::Microprogrammed {
+                microcode_program_counter: *microcode_program_counter,
+                writable_control_store: microcode[WRITABLE_CONTROL_STORE]
+                    .iter()
+                    .map(encode_microinstruction)
+                    .collect(),
+            },
+            ControlUnit::Hardwired(control_unit) => ControlUnitState::Hardwired(*control_unit),
+        }
+    }
+
+    /// Writable control store content is verified the same way WCS does
+    pub fn restore(&mut self, state: ControlUnitState) -> Result<(), MicrocodeError> {
+        match (self, state) {
+            (
+                ControlUnit::Microprogrammed {
+                    microcode,
+                    microcode_program_counter,
+                },
+                ControlUnitState::Microprogrammed {
+                    microcode_program_counter: restored_counter,
+                    writable_control_store,
+                },
+            ) => {
+                if writable_control_store.len() != WRITABLE_CONTROL_STORE_SIZE {
+                    return Err(MicrocodeError::ControlStoreSizeMismatch {
+                        size: writable_control_store.len(),
+                    });
+                }
+                if restored_counter >= microcode.len() {
+                    return Err(MicrocodeError::CounterOutOfRange {
+                        counter: restored_counter,
+                        microcode_size: microcode.len(),
+                    });
+                }
+
+                let mut restored = Vec::with_capacity(WRITABLE_CONTROL_STORE_SIZE);
+                for (slot, &encoded) in writable_control_store.iter().enumerate() {
+                    let micro_instruction = decode_microinstruction(encoded);
+                    verify_user_microinstruction(slot, &micro_instruction)?;
+                    restored.push(micro_instruction);
+                }
+
+                microcode.splice(WRITABLE_CONTROL_STORE, restored);
+                *microcode_program_counter = restored_counter;
+
+                Ok(())
+            }
+            (ControlUnit::Hardwired(control_unit), ControlUnitState::Hardwired(restored)) => {
+                *control_unit = restored;
+
+                Ok(())
+            }
+            _ => Err(MicrocodeError::StateMismatch),
+        }
+    }
+
+    /// Loads encoded microinstruction into writable control store.
+    /// Rejected microinstruction leaves control store untouched
+    pub fn write_control_store(&mut self, slot: usize, encoded: u32) -> Result<(), MicrocodeError> {
+        let ControlUnit::Microprogrammed { microcode, .. } = self else {
+            return Err(MicrocodeError::NoControlStore);
+        };
+
+        let micro_instruction = decode_microinstruction(encoded);
+        verify_user_microinstruction(slot, &micro_instruction)?;
+
+        let address = WRITABLE_CONTROL_STORE_START + slot;
+        log::info!(
+            "Control store [{}] <- {}",
+            address,
+            format_microinstruction(&micro_instruction)
+        );
+        microcode[address] = micro_instruction;
+
+        Ok(())
+    }
+}
+
+fn opcode_to_mc(opcode: Opcode) -> MicroInstructionCounter {
+    // security mechanism is required here
+    // each table entry should have bitmask of allowed argument types
+    // if argument is not allowed, then processor should throw
+    // security exception
+    // after all, this is cratch too.
+    // proper way would be to introduce command formats.
+    // This is too complicated for the lab, so leaving it as is
+    match opcode {
+        Opcode::IN => 13,
+        Opcode::OUT => 14,
+        Opcode::LOAD => 15,
+        Opcode::STORE => 16,
+        Opcode::ADD => 18,
+        Opcode::INC => 19,
+        Opcode::AND => 20,
+        Opcode::CMP => 21,
+        Opcode::SHIFT_LEFT => 22,
+        Opcode::SHIFT_RIGHT => 23,
+        Opcode::JZC => 24,
+        Opcode::JZS => 25,
+        Opcode::JCC => 26,
+        Opcode::JCS => 27,
+        Opcode::JUMP => 28,
+        // just fetch next instruction
+        Opcode::NOP => 0,
+        Opcode::HALT => 29,
+        Opcode::TAS => TAS_ROUTINE,
+        Opcode::COREID => COREID_ROUTINE,
+        Opcode::WCS => WCS_ROUTINE,
+        Opcode::USER(index) => user_routine_start(index),
+    }
+}
+
+fn operand_type_to_mc(operand: OperandType) -> MicroInstructionCounter {
+    match operand {
+        OperandType::None => 3,
+        OperandType::Indirect => 9,
+        OperandType::Absolute => 5,
+        OperandType::Relative => 7,
+        OperandType::Immediate => 4,
+    }
+}
+
+pub fn encode_microinstruction(micro_instruction: &Microinstruction) -> u32 {
+    Signal::ALL
+        .iter()
+        .enumerate()
+        .filter(|(_, signal)| micro_instruction.contains(signal))
+        .fold(0, |encoded, (bit, _)| encoded | 1 << bit)
+}
+
+/// Bits which do not correspond to any signal are ignored
+pub fn decode_microinstruction(encoded: u32) -> Microinstruction {
+    Signal::ALL
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| encoded & 1 << bit != 0)
+        .map(|(_, &signal)| signal)
+        .collect()
+}
+
+/// `HashSet` has no stable order, so signals are printed in encoding order
+pub fn format_microinstruction(micro_instruction: &Microinstruction) -> String {
+    Signal::ALL
+        .iter()
+        .filter(|signal| micro_instruction.contains(signal))
+        .map(|signal| format!("{signal:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// control store layout
+// [0; WCS_ROUTINE] - microcode burnt into ROM
+// WRITABLE_CONTROL_STORE - USER_OPCODES routines of
+// USER_ROUTINE_SIZE microinstructions each
+// [TAS_ROUTINE; ..) - ROM extension. Appended after writable control
+// store, so addresses of existing routines stay the same
+pub const WCS_ROUTINE: MicroInstructionCounter = 30;
+pub const WRITABLE_CONTROL_STORE_START: MicroInstructionCounter = 31;
+pub const USER_ROUTINE_SIZE: usize = 8;
+pub const WRITABLE_CONTROL_STORE_SIZE: usize = USER_OPCODES as usize * USER_ROUTINE_SIZE;
+pub const WRITABLE_CONTROL_STORE: Range<MicroInstructionCounter> =
+    WRITABLE_CONTROL_STORE_START..WRITABLE_CONTROL_STORE_START + WRITABLE_CONTROL_STORE_SIZE;
+pub const TAS_ROUTINE: MicroInstructionCounter = WRITABLE_CONTROL_STORE.end;
+pub const COREID_ROUTINE: MicroInstructionCounter = TAS_ROUTINE + 3;
+
+pub fn user_routine_start(index: u8) -> MicroInstructionCounter {
+    WRITABLE_CONTROL_STORE_START + index as usize * USER_ROUTINE_SIZE
+}
+
+/// Returns user routine and step within it for microinstructions
+/// located in writable control store
+pub fn user_routine_position(
+    microcode_program_counter: MicroInstructionCounter,
+) -> Option<(u8, usize)> {
+    if !WRITABLE_CONTROL_STORE.contains(&microcode_program_counter) {
+        return None;
+    }
+    let offset = microcode_program_counter - WRITABLE_CONTROL_STORE_START;
+
+    Some((
+        (offset / USER_ROUTINE_SIZE) as u8,
+        offset % USER_ROUTINE_SIZE,
+    ))
+}
+
+/// Name of microroutine which microinstruction at `address` belongs to.
+/// Routine spans from its dispatch target up to the next routine
+pub fn routine_name(address: MicroInstructionCounter) -> String {
+    let operand_routines = OperandType::ALL.map(|operand_type| {
+        (
+            operand_type_to_mc(operand_type),
+            format!("operand-{operand_type:?}").to_lowercase(),
+        )
+    });
+    let opcode_routines = Opcode::ALL
+        .into_iter()
+        // NOP dispatches straight to fetch
+        .filter(|&opcode| opcode != Opcode::NOP)
+        .map(|opcode| (opcode_to_mc(opcode), opcode.to_string()));
+
+    std::iter::once((0, "fetch".to_owned()))
+        .chain(operand_routines)
+        .chain(opcode_routines)
+        .filter(|&(start, _)| start <= address)
+        .max_by_key(|&(start, _)| start)
+        .map(|(_, name)| name)
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+pub enum MicrocodeError {
+    OutOfControlStore {
+        slot: usize,
+    },
+    FallsOffRoutine {
+        address: MicroInstructionCounter,
+    },
+    ForbiddenSignal {
+        address: MicroInstructionCounter,
+        signal: Signal,
+    },
+    ForbiddenDispatch {
+        address: MicroInstructionCounter,
+    },
+    NoControlStore,
+    /// state of microprogrammed control unit is restored into hardwired one or vice versa
+    StateMismatch,
+    /// restored writable control store is of other size
+    ControlStoreSizeMismatch {
+        size: usize,
+    },
+    /// restored microprogram counter points past microcode
+    CounterOutOfRange {
+        counter: usize,
+        microcode_size: usize,
+    },
+}
+
+impl Error for MicrocodeError {}
+
+impl Display for MicrocodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MicrocodeError::OutOfControlStore { slot } => {
+                write!(
+                    f,
+                    "slot {slot} is outside of writable control store of {WRITABLE_CONTROL_STORE_SIZE} microinstructions"
+                )
+            }
+            MicrocodeError::FallsOffRoutine { address } => {
+                write!(
+                    f,
+                    "microinstruction {address} continues past the end of its routine"
+                )
+            }
+            MicrocodeError::ForbiddenSignal { address, signal } => {
+                write!(f, "microinstruction {address} uses {signal:?}, which is not allowed in user routines")
+            }
+            MicrocodeError::ForbiddenDispatch { address } => {
+                write!(f, "microinstruction {address} dispatches by operand type or opcode, user routines may only continue or return to fetch")
+            }
+            MicrocodeError::NoControlStore => {
+                write!(f, "hardwired control unit has no control store")
+            }
+            MicrocodeError::StateMismatch => {
+                write!(f, "state belongs to other kind of control unit")
+            }
+            MicrocodeError::ControlStoreSizeMismatch { size } => {
+                write!(
+                    f,
+                    "state holds {size} microinstructions of writable control store, expected {WRITABLE_CONTROL_STORE_SIZE}"
+                )
+            }
+            MicrocodeError::CounterOutOfRange {
+                counter,
+                microcode_size,
+            } => {
+                write!(
+                    f,
+                    "microprogram counter {counter} is outside of microcode of {microcode_size} microinstructions"
+                )
+            }
+        }
+    }
+}
+
+/// Checks that every microinstruction of control store is sequenced into
+/// control store. User routines are held to stricter rules, see
+/// [`verify_user_microinstruction`]
+pub fn verify_microcode(microcode: &MicrocodeStorage) -> Result<(), MicrocodeError> {
+    for (address, micro_instruction) in microcode.iter().enumerate() {
+        if WRITABLE_CONTROL_STORE.contains(&address) {
+            verify_user_microinstruction(
+                address - WRITABLE_CONTROL_STORE_START,
+                micro_instruction,
+            )?;
+            continue;
+        }
+
+        let continues = !micro_instruction.contains(&Signal::SELECT_MC_0)
+            && !micro_instruction.contains(&Signal::SELECT_MC_1);
+        if continues
+            && (address + 1 == WRITABLE_CONTROL_STORE_START || address + 1 == microcode.len())
+        {
+            return Err(MicrocodeError::FallsOffRoutine { address });
+        }
+    }
+
+    Ok(())
+}
+
+/// User routines may only continue to the next microinstruction of
+/// the same routine or return to instruction fetch. They may not rewrite
+/// control store themselves.
+pub fn verify_user_microinstruction(
+    slot: usize,
+    micro_instruction: &Microinstruction,
+) -> Result<(), MicrocodeError> {
+    if slot >= WRITABLE_CONTROL_STORE_SIZE {
+        return Err(MicrocodeError::OutOfControlStore { slot });
+    }
+
+    let address = WRITABLE_CONTROL_STORE_START + slot;
+    let step = slot % USER_ROUTINE_SIZE;
+
+    for signal in [Signal::WRITE_CONTROL_STORE, Signal::WRITE_COMMAND] {
+        if micro_instruction.contains(&signal) {
+            return Err(MicrocodeError::ForbiddenSignal { address, signal });
+        }
+    }
+
+    match (
+        micro_instruction.contains(&Signal::SELECT_MC_1),
+        micro_instruction.contains(&Signal::SELECT_MC_0),
+    ) {
+        (false, false) if step + 1 == USER_ROUTINE_SIZE => {
+            Err(MicrocodeError::FallsOffRoutine { address })
+        }
+        (true, _) => Err(MicrocodeError::ForbiddenDispatch { address }),
+        _ => Ok(()),
+    }
+}
+
 macro_rules! mc {
     ($($signals:ident),+) => {
         Microinstruction::from([$($signals),+])
@@ -88,7 +644,7 @@ macro_rules! mc {
 
 pub fn get_microcode() -> MicrocodeStorage {
     use Signal::*;
-    vec![
+    let mut microcode = vec![
         // instruction fetch
         // pc -> addr
         /* 0 */
@@ -268,5 +824,40 @@ pub fn get_microcode() -> MicrocodeStorage {
         // HALT
         /* 29 */
         mc![HALT, SELECT_MC_0],
-    ]
+        // ----
+
+        // microcode
+        // WCS
+        /* 30 */
+        mc![WRITE_CONTROL_STORE, SELECT_MC_0],
+    ];
+
+    // writable control store
+    // until loaded, user routines just fetch next instruction
+    microcode.resize(WRITABLE_CONTROL_STORE.end, mc![SELECT_MC_0]);
+
+    microcode.extend([
+        // multi-core
+        // TAS
+        // memory bus is locked from fetch of TAS till next fetch,
+        // so no other core accesses memory in between
+        // acc <- mem[addr]; Z is set if lock was free
+        /* 95 */
+        mc![
+            ZERO_LEFT,
+            SELECT_RIGHT_DATA,
+            WRITE_ACCUMULATOR,
+            WRITE_STATUS
+        ],
+        // data <- 1
+        /* 96 */
+        mc![ZERO_LEFT, INC, WRITE_DATA],
+        /* 97 */
+        mc![WRITE_MEM, SELECT_MC_0],
+        // COREID
+        /* 98 */
+        mc![ZERO_LEFT, SELECT_CORE_ID, WRITE_ACCUMULATOR, SELECT_MC_0],
+    ]);
+
+    microcode
 }
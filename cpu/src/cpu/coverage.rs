@@ -0,0 +1,181 @@
+//! Microcode coverage: how many times every control store address
+//! and every signal was exercised. Coverage of several runs merges
+//! into one, so a whole test suite may accumulate into a single file.
+
+use std::{collections::BTreeMap, error::Error, fmt::Display, fmt::Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    control_unit::{routine_name, Microinstruction, Signal},
+    MicroInstructionCounter,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MicrocodeCoverage {
+    pub runs: usize,
+    /// hits per control store address.
+    /// Empty for hardwired control unit
+    pub addresses: Vec<u64>,
+    /// hits per signal, keyed by signal name
+    pub signals: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub enum CoverageError {
+    ControlStoreMismatch { expected: usize, actual: usize },
+}
+
+impl Error for CoverageError {}
+
+impl Display for CoverageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoverageError::ControlStoreMismatch { expected, actual } => writeln!(
+                f,
+                "Coverage recorded for control store of {actual} microinstructions \
+                 can not be merged with control store of {expected}"
+            ),
+        }
+    }
+}
+
+impl MicrocodeCoverage {
+    /// Coverage of single run
+    pub fn new(control_store_size: usize) -> Self {
+        Self {
+            runs: 1,
+            addresses: vec![0; control_store_size],
+            signals: Signal::ALL
+                .iter()
+                .map(|signal| (format!("{signal:?}"), 0))
+                .collect(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        address: Option<MicroInstructionCounter>,
+        micro_instruction: &Microinstruction,
+    ) {
+        if let Some(hits) = address.and_then(|address| self.addresses.get_mut(address)) {
+            *hits += 1;
+        }
+
+        for signal in micro_instruction {
+            *self.signals.entry(format!("{signal:?}")).or_default() += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &MicrocodeCoverage) -> Result<(), CoverageError> {
+        if self.addresses.is_empty() {
+            self.addresses = vec![0; other.addresses.len()];
+        }
+
+        // hardwired runs contribute signals only
+        if !other.addresses.is_empty() {
+            if self.addresses.len() != other.addresses.len() {
+                return Err(CoverageError::ControlStoreMismatch {
+                    expected: self.addresses.len(),
+                    actual: other.addresses.len(),
+                });
+            }
+
+            for (hits, other_hits) in self.addresses.iter_mut().zip(&other.addresses) {
+                *hits += other_hits;
+            }
+        }
+
+        for (signal, hits) in &other.signals {
+            *self.signals.entry(signal.clone()).or_default() += hits;
+        }
+
+        self.runs += other.runs;
+
+        Ok(())
+    }
+
+    /// Consecutive addresses of the same routine: (routine, first, last)
+    fn routines(&self) -> Vec<(String, usize, usize)> {
+        let mut routines: Vec<(String, usize, usize)> = Vec::new();
+        for address in 0..self.addresses.len() {
+            let name = routine_name(address);
+            match routines.last_mut() {
+                Some((last_name, _, last)) if *last_name == name => *last = address,
+                _ => routines.push((name, address, address)),
+            }
+        }
+
+        routines
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        let exercised = self.addresses.iter().filter(|&&hits| hits > 0).count();
+
+        let _ = writeln!(output, "Microcode coverage over {} run(s)", self.runs);
+        let _ = writeln!(
+            output,
+            "Control store: {exercised} of {} microinstructions exercised",
+            self.addresses.len()
+        );
+
+        let mut unexercised = Vec::new();
+        if !self.addresses.is_empty() {
+            output.push('\n');
+            let _ = writeln!(
+                output,
+                "{:<20}{:>12}{:>12}{:>12}",
+                "routine", "addresses", "exercised", "hits"
+            );
+        }
+
+        for (name, first, last) in self.routines() {
+            let routine = &self.addresses[first..=last];
+            let routine_exercised = routine.iter().filter(|&&hits| hits > 0).count();
+            let _ = writeln!(
+                output,
+                "{:<20}{:>12}{:>12}{:>12}",
+                name,
+                format!("{first}..={last}"),
+                format!("{routine_exercised}/{}", routine.len()),
+                routine.iter().sum::<u64>()
+            );
+
+            // collapse runs of unexercised addresses into ranges
+            let mut range: Option<(usize, usize)> = None;
+            for (address, &hits) in (first..=last).zip(routine) {
+                match (&mut range, hits) {
+                    (Some((_, end)), 0) => *end = address,
+                    (None, 0) => range = Some((address, address)),
+                    (Some(_), _) => unexercised.extend(range.take().map(|r| (name.clone(), r))),
+                    (None, _) => (),
+                }
+            }
+            unexercised.extend(range.map(|r| (name.clone(), r)));
+        }
+
+        if !unexercised.is_empty() {
+            output.push('\n');
+            let _ = writeln!(output, "Unexercised microinstructions:");
+            for (name, (start, end)) in unexercised {
+                let _ = writeln!(output, "{:>12} {name}", format!("{start}..={end}"));
+            }
+        }
+
+        output.push('\n');
+        let _ = writeln!(output, "{:<32}{:>12}", "signal", "hits");
+        for signal in Signal::ALL {
+            let name = format!("{signal:?}");
+            let hits = self.signals.get(&name).copied().unwrap_or_default();
+            let mark = if hits == 0 { "  never issued" } else { "" };
+            let _ = writeln!(output, "{name:<32}{hits:>12}{mark}");
+        }
+
+        output
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
@@ -1,10 +1,11 @@
 use std::fmt::Display;
 
 use isa::{CompiledCommand, MemoryItem, Opcode::NOP, Operand, OperandType::None, RawAddress};
+use serde::{Deserialize, Serialize};
 
 use super::TRegisterValue;
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Registers {
     pub accumulator: TRegisterValue,
     pub data: MemoryItem,
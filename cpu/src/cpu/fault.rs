@@ -0,0 +1,90 @@
+//! Conditions which stop CPU because program did something
+//! hardware can not do, e.g. executed binary data
+
+use std::{error::Error, fmt::Display};
+
+use isa::{CompiledCommand, RawAddress, RawPort};
+use serde::{Deserialize, Serialize};
+
+use super::{MicroInstructionCounter, MAX_EXIT_CODE};
+use crate::protection::Violation;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultCause {
+    /// instruction cell was used as a number
+    InstructionAsData { command: CompiledCommand },
+    /// binary data was latched into command register
+    DataAsInstruction { value: u32 },
+    /// device refused to provide data
+    DeviceRead { port: RawPort },
+    /// command has no microroutine, e.g. USER beyond writable control store
+    InvalidOpcode { command: CompiledCommand },
+    /// memory protection refused access to address register
+    Protection(Violation),
+    /// HALT operand does not fit into exit codes of program
+    InvalidExitCode { exit_code: u32 },
+}
+
+impl Display for FaultCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FaultCause::InstructionAsData { command } => {
+                write!(f, "instruction used as data: {command}")
+            }
+            FaultCause::DataAsInstruction { value } => {
+                write!(
+                    f,
+                    "tried to write binary data {value} into command register"
+                )
+            }
+            FaultCause::DeviceRead { port } => write!(f, "could not read from device {port}"),
+            FaultCause::InvalidOpcode { command } => write!(f, "invalid opcode: {command}"),
+            FaultCause::Protection(violation) => write!(f, "memory protection: {violation}"),
+            FaultCause::InvalidExitCode { exit_code } => {
+                write!(f, "exit code {exit_code} is above {MAX_EXIT_CODE}")
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutedInstruction {
+    pub address: RawAddress,
+    pub command: CompiledCommand,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CpuFault {
+    pub cause: FaultCause,
+    pub program_counter: RawAddress,
+    pub address: RawAddress,
+    /// control store address. Hardwired control unit has none
+    pub micro_instruction: Option<MicroInstructionCounter>,
+    pub tick: usize,
+    /// oldest first, faulting instruction is the last one
+    pub recent_instructions: Vec<ExecutedInstruction>,
+}
+
+impl Error for CpuFault {}
+
+impl Display for CpuFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "CPU fault: {}", self.cause)?;
+        write!(
+            f,
+            "  tick {}, program counter {:#06x}, address {:#06x}",
+            self.tick, self.program_counter, self.address
+        )?;
+        match self.micro_instruction {
+            Some(micro_instruction) => writeln!(f, ", microinstruction {micro_instruction}")?,
+            None => writeln!(f)?,
+        }
+
+        writeln!(f, "Last executed instructions:")?;
+        for ExecutedInstruction { address, command } in &self.recent_instructions {
+            writeln!(f, "  {address:#06x} {command}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Hardwired alternative to microprogrammed control unit.
+//! Every signal is a boolean function of phase, step counter,
+//! operand type and opcode. Sequencing is driven by the same
+//! SELECT_MC signals microcode uses, so both units produce
+//! identical signal sets for every tick.
+
+use std::fmt::Display;
+
+use isa::{CompiledCommand, Opcode, OperandType};
+use serde::{Deserialize, Serialize};
+
+use super::control_unit::{Microinstruction, Signal};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Fetch,
+    OperandFetch,
+    Execution,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwiredControlUnit {
+    phase: Phase,
+    step: u8,
+}
+
+impl Default for HardwiredControlUnit {
+    fn default() -> Self {
+        Self {
+            phase: Phase::Fetch,
+            step: 0,
+        }
+    }
+}
+
+impl Display for HardwiredControlUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} step {}", self.phase, self.step)
+    }
+}
+
+impl HardwiredControlUnit {
+    pub fn is_fetching_instruction(&self) -> bool {
+        self.phase == Phase::Fetch && self.step == 0
+    }
+
+    pub fn signals(&self, command: &CompiledCommand) -> Microinstruction {
+        use Opcode::*;
+
+        let &Self { phase, step } = self;
+        let opcode = command.opcode;
+        let operand_type = command.operand.operand_type;
+
+        // decoder outputs
+        let fetch = |n| phase == Phase::Fetch && step == n;
+        let operand = |kind, n| phase == Phase::OperandFetch && operand_type == kind && step == n;
+        let execute = |code, n| phase == Phase::Execution && opcode == code && step == n;
+        let execute_any = |codes: &[Opcode]| codes.iter().any(|&code| execute(code, 0));
+        let execute_user = phase == Phase::Execution && matches!(opcode, USER(_)) && step == 0;
+
+        let none_0 = operand(OperandType::None, 0);
+        let immediate_0 = operand(OperandType::Immediate, 0);
+        let absolute_0 = operand(OperandType::Absolute, 0);
+        let absolute_1 = operand(OperandType::Absolute, 1);
+        let relative_0 = operand(OperandType::Relative, 0);
+        let relative_1 = operand(OperandType::Relative, 1);
+        let indirect_0 = operand(OperandType::Indirect, 0);
+        let indirect_1 = operand(OperandType::Indirect, 1);
+        let indirect_2 = operand(OperandType::Indirect, 2);
+        let indirect_3 = operand(OperandType::Indirect, 3);
+
+        let operand_done = none_0 || immediate_0 || absolute_1 || relative_1 || indirect_3;
+        let jump = execute_any(&[JZC, JZS, JCC, JCS, JUMP]);
+        let execution_done = execute_any(&[
+            IN,
+            OUT,
+            LOAD,
+            ADD,
+            INC,
+            AND,
+            CMP,
+            SHIFT_LEFT,
+            SHIFT_RIGHT,
+            HALT,
+            WCS,
+            COREID,
+        ]) || execute(STORE, 1)
+            || execute(TAS, 2)
+            || jump
+            // there is no control store to dispatch into
+            || execute_user;
+
+        let equations = [
+            (Signal::IO, execute_any(&[IN, OUT])),
+            (Signal::WRITE_IO, execute(OUT, 0)),
+            (
+                Signal::SELECT_MEM,
+                fetch(1) || absolute_1 || relative_1 || indirect_1 || indirect_3,
+            ),
+            (Signal::WRITE_MEM, execute(STORE, 1) || execute(TAS, 2)),
+            (
+                Signal::WRITE_ACCUMULATOR,
+                execute_any(&[
+                    IN,
+                    LOAD,
+                    ADD,
+                    INC,
+                    AND,
+                    SHIFT_LEFT,
+                    SHIFT_RIGHT,
+                    TAS,
+                    COREID,
+                ]),
+            ),
+            (
+                Signal::WRITE_DATA,
+                fetch(1)
+                    || immediate_0
+                    || absolute_1
+                    || relative_1
+                    || indirect_1
+                    || indirect_3
+                    || execute(STORE, 0)
+                    || execute(TAS, 1),
+            ),
+            (
+                Signal::WRITE_ADDRESS,
+                fetch(0) || immediate_0 || absolute_0 || relative_0 || indirect_0 || indirect_2,
+            ),
+            (
+                Signal::WRITE_STATUS,
+                execute_any(&[ADD, INC, AND, CMP, TAS]),
+            ),
+            (Signal::WRITE_COMMAND, fetch(2)),
+            (Signal::WRITE_PROGRAM_COUNTER, fetch(1) || execute(JUMP, 0)),
+            (Signal::WRITE_PROGRAM_COUNTER_Z, execute_any(&[JZC, JZS])),
+            (Signal::WRITE_PROGRAM_COUNTER_C, execute_any(&[JCC, JCS])),
+            (
+                Signal::WRITE_PROGRAM_COUNTER_CLEAR,
+                execute_any(&[JZC, JCC]),
+            ),
+            (Signal::AND, execute(AND, 0)),
+            (Signal::NOT_LEFT, false),
+            (Signal::NOT_RIGHT, execute(CMP, 0)),
+            (
+                Signal::INC,
+                fetch(1) || execute_any(&[INC, CMP]) || execute(TAS, 1),
+            ),
+            (Signal::SHIFT, execute_any(&[SHIFT_LEFT, SHIFT_RIGHT])),
+            (Signal::SHIFT_LEFT, execute(SHIFT_LEFT, 0)),
+            (
+                Signal::ZERO_LEFT,
+                immediate_0
+                    || absolute_0
+                    || indirect_2
+                    || execute_any(&[LOAD, TAS, COREID])
+                    || execute(TAS, 1)
+                    || jump,
+            ),
+            (
+                Signal::SELECT_PC,
+                fetch(0) || fetch(1) || relative_0 || indirect_0,
+            ),
+            (
+                Signal::SELECT_RIGHT_CMD_OPERAND,
+                immediate_0 || absolute_0 || relative_0 || indirect_0 || jump,
+            ),
+            (
+                Signal::SELECT_RIGHT_DATA,
+                indirect_2 || execute_any(&[LOAD, ADD, AND, CMP, TAS]) || jump,
+            ),
+            (Signal::SELECT_MC_1, fetch(2) || operand_done),
+            (Signal::SELECT_MC_0, operand_done || execution_done),
+            (Signal::HALT, execute(HALT, 0)),
+            (Signal::WRITE_CONTROL_STORE, execute(WCS, 0)),
+            (Signal::SELECT_CORE_ID, execute(COREID, 0)),
+        ];
+
+        equations
+            .into_iter()
+            .filter(|&(_, active)| active)
+            .map(|(signal, _)| signal)
+            .collect()
+    }
+
+    /// Step counter is reset on phase change, incremented otherwise.
+    /// NOP has no execution phase and goes straight to fetch
+    pub fn advance(&mut self, micro_instruction: &Microinstruction, command: &CompiledCommand) {
+        let mc_0 = micro_instruction.contains(&Signal::SELECT_MC_0) as u8;
+        let mc_1 = (micro_instruction.contains(&Signal::SELECT_MC_1) as u8) << 1;
+
+        let phase = match mc_1 | mc_0 {
+            0b00 => {
+                self.step += 1;
+                return;
+            }
+            0b01 => Phase::Fetch,
+            0b10 => Phase::OperandFetch,
+            0b11 if command.opcode == Opcode::NOP => Phase::Fetch,
+            0b11 => Phase::Execution,
+            _ => unreachable!(),
+        };
+
+        *self = Self { phase, step: 0 };
+    }
+}
@@ -0,0 +1,269 @@
+//! Reverse execution. Every tick records what it is about to overwrite,
+//! so it can be undone. Deltas are kept only since the last keyframe:
+//! going further back restores previous keyframe and replays forward,
+//! which keeps memory use bounded
+
+use std::collections::VecDeque;
+
+use isa::{MemoryItem, RawAddress};
+
+use crate::{cache::CacheHierarchy, io_controller::IOControllerState};
+
+use super::{
+    control_unit::ControlUnitState, hardwired::HardwiredControlUnit, ControlUnit, Cpu,
+    ExecutedInstruction, ExecutionCounters, HaltReason, Registers, Signal, Snapshot, Status,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryConfig {
+    /// ticks between keyframes. Longest replay is one interval
+    pub keyframe_interval: usize,
+    /// oldest keyframes are dropped, so history reaches back
+    /// at most `keyframe_interval * max_keyframes` ticks
+    pub max_keyframes: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            keyframe_interval: 1024,
+            max_keyframes: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Sequencer {
+    Microprogrammed(usize),
+    Hardwired(HardwiredControlUnit),
+}
+
+/// State tick is about to overwrite
+#[derive(Debug)]
+struct TickDelta {
+    registers: Registers,
+    status: Status,
+    sequencer: Sequencer,
+    counters: ExecutionCounters,
+    halt_reason: Option<HaltReason>,
+    memory_wait: Option<usize>,
+    fetching: bool,
+    memory_write: Option<(RawAddress, MemoryItem)>,
+    control_store: Option<ControlUnitState>,
+    devices: Option<IOControllerState>,
+    recent_instructions: Option<VecDeque<ExecutedInstruction>>,
+    caches: Option<CacheHierarchy>,
+}
+
+/// Tick which was undone
+#[derive(Debug, Clone, Copy)]
+pub struct UndoneTick {
+    /// memory cell the tick wrote
+    pub memory_write: Option<RawAddress>,
+}
+
+#[derive(Debug)]
+pub struct History {
+    config: HistoryConfig,
+    /// ticks recorded since history start
+    position: usize,
+    keyframes: VecDeque<(usize, Snapshot)>,
+    /// deltas since the last keyframe
+    deltas: Vec<TickDelta>,
+}
+
+impl History {
+    /// History starts at current state of CPU, it can not go further back
+    pub fn new(cpu: &Cpu, config: HistoryConfig) -> Self {
+        Self {
+            config: HistoryConfig {
+                keyframe_interval: config.keyframe_interval.max(1),
+                max_keyframes: config.max_keyframes.max(1),
+            },
+            position: 0,
+            keyframes: VecDeque::from([(0, cpu.snapshot())]),
+            deltas: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> HistoryConfig {
+        self.config
+    }
+
+    /// Executes single tick, recording it. Same result as `Cpu::tick`
+    pub fn tick(&mut self, cpu: &mut Cpu) -> bool {
+        if cpu.halt_reason.is_some() {
+            return false;
+        }
+
+        let delta = TickDelta::before(cpu);
+        let running = cpu.tick();
+
+        self.deltas.push(delta);
+        self.position += 1;
+        if self.deltas.len() >= self.config.keyframe_interval {
+            self.deltas.clear();
+            self.keyframes.push_back((self.position, cpu.snapshot()));
+            if self.keyframes.len() > self.config.max_keyframes {
+                self.keyframes.pop_front();
+            }
+        }
+
+        running
+    }
+
+    /// `None` when history does not reach further back
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Option<UndoneTick> {
+        if self.deltas.is_empty() {
+            self.rewind_to_previous_keyframe(cpu)?;
+        }
+
+        let delta = self.deltas.pop()?;
+        self.position -= 1;
+
+        Some(delta.undo(cpu))
+    }
+
+    /// Restores keyframe preceding current position and replays up to it,
+    /// so deltas of the last interval are available again
+    fn rewind_to_previous_keyframe(&mut self, cpu: &mut Cpu) -> Option<()> {
+        // current state is the last keyframe
+        if self.keyframes.len() < 2 {
+            return None;
+        }
+        self.keyframes.pop_back();
+
+        let (keyframe_position, keyframe) = self.keyframes.back()?.clone();
+        cpu.restore(keyframe).ok()?;
+
+        let target = self.position;
+        self.position = keyframe_position;
+        replay(cpu, |cpu| {
+            while self.position < target {
+                self.deltas.push(TickDelta::before(cpu));
+                self.position += 1;
+                cpu.tick();
+            }
+        });
+
+        Some(())
+    }
+
+    /// Executes stepped back ticks again, e.g. when search through
+    /// history found nothing. Keyframes dropped on the way back are
+    /// recorded anew
+    pub fn redo(&mut self, cpu: &mut Cpu, ticks: usize) {
+        replay(cpu, |cpu| {
+            for _ in 0..ticks {
+                self.tick(cpu);
+            }
+        });
+    }
+}
+
+/// Replay is not part of the run: nothing is logged, counted
+/// or delivered to devices twice
+fn replay(cpu: &mut Cpu, ticks: impl FnOnce(&mut Cpu)) {
+    let log_ticks = cpu.log_ticks;
+    let microcode_coverage = cpu.microcode_coverage.take();
+    cpu.log_ticks = false;
+    cpu.io_controller.set_replay(true);
+    ticks(cpu);
+    cpu.io_controller.set_replay(false);
+    cpu.microcode_coverage = microcode_coverage;
+    cpu.log_ticks = log_ticks;
+}
+
+impl TickDelta {
+    fn before(cpu: &Cpu) -> Self {
+        let micro_instruction = cpu.current_microinstruction();
+        let accesses_memory = micro_instruction.contains(&Signal::WRITE_MEM)
+            || micro_instruction.contains(&Signal::SELECT_MEM)
+                && micro_instruction.contains(&Signal::WRITE_DATA);
+        let sequencer = match &cpu.control_unit {
+            ControlUnit::Microprogrammed {
+                microcode_program_counter,
+                ..
+            } => Sequencer::Microprogrammed(*microcode_program_counter),
+            ControlUnit::Hardwired(control_unit) => Sequencer::Hardwired(*control_unit),
+        };
+
+        Self {
+            registers: cpu.registers.clone(),
+            status: cpu.status,
+            sequencer,
+            counters: cpu.counters(),
+            halt_reason: cpu.halt_reason.clone(),
+            memory_wait: cpu.memory_wait,
+            fetching: cpu.fetching,
+            memory_write: micro_instruction
+                .contains(&Signal::WRITE_MEM)
+                .then(|| (cpu.registers.address, cpu.memory[cpu.registers.address])),
+            control_store: micro_instruction
+                .contains(&Signal::WRITE_CONTROL_STORE)
+                .then(|| cpu.control_unit.state()),
+            devices: micro_instruction
+                .contains(&Signal::IO)
+                .then(|| cpu.io_controller.state()),
+            recent_instructions: micro_instruction
+                .contains(&Signal::WRITE_COMMAND)
+                .then(|| cpu.recent_instructions.clone()),
+            // cache is accessed once, when memory access is issued
+            caches: cpu
+                .caches
+                .as_ref()
+                .filter(|_| accesses_memory && cpu.memory_wait.is_none())
+                .cloned(),
+        }
+    }
+
+    fn undo(self, cpu: &mut Cpu) -> UndoneTick {
+        cpu.registers = self.registers;
+        cpu.status = self.status;
+        cpu.instructions_executed = self.counters.instructions;
+        cpu.micro_instructions_executed = self.counters.ticks;
+        cpu.stall_ticks = self.counters.stall_ticks;
+        cpu.memory_wait = self.memory_wait;
+        cpu.fetching = self.fetching;
+        cpu.halt_reason = self.halt_reason;
+
+        if let Some(control_store) = self.control_store {
+            // state was taken from this very control unit
+            let _ = cpu.control_unit.restore(control_store);
+        }
+        match (&mut cpu.control_unit, self.sequencer) {
+            (
+                ControlUnit::Microprogrammed {
+                    microcode_program_counter,
+                    ..
+                },
+                Sequencer::Microprogrammed(restored),
+            ) => *microcode_program_counter = restored,
+            (ControlUnit::Hardwired(control_unit), Sequencer::Hardwired(restored)) => {
+                *control_unit = restored
+            }
+            _ => unreachable!("control unit kind does not change during execution"),
+        }
+
+        if let Some((address, item)) = self.memory_write {
+            cpu.memory[address] = item;
+        }
+        if let Some(devices) = self.devices {
+            // state was taken from the same devices
+            let _ = cpu.io_controller.restore(devices);
+        }
+        if let Some(caches) = self.caches {
+            cpu.caches = Some(caches);
+        }
+        if let Some(recent_instructions) = self.recent_instructions {
+            cpu.recent_instructions = recent_instructions;
+        }
+        if let Some(loop_detector) = &mut cpu.loop_detector {
+            loop_detector.forget();
+        }
+
+        UndoneTick {
+            memory_write: self.memory_write.map(|(address, _)| address),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Guards against programs which never stop
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use super::{ExecutionCounters, HaltReason, Registers, Status};
+
+// reading clock on every tick is too expensive
+const TIME_CHECK_PERIOD: usize = 1024;
+// bounds memory used by loop detection. Overflow forgets seen states,
+// so loop is detected later, but never falsely
+const MAX_SEEN_STATES: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionLimits {
+    pub max_ticks: Option<usize>,
+    pub max_instructions: Option<usize>,
+    pub max_time: Option<Duration>,
+    /// stop when architectural state repeats at instruction boundary
+    pub detect_loops: bool,
+}
+
+impl ExecutionLimits {
+    /// Reason to stop before executing next tick
+    pub fn exceeded(&self, counters: ExecutionCounters, started: Instant) -> Option<HaltReason> {
+        if let Some(limit) = self.max_ticks.filter(|&limit| counters.ticks >= limit) {
+            return Some(HaltReason::TickLimit { limit });
+        }
+
+        if let Some(limit) = self
+            .max_instructions
+            .filter(|&limit| counters.instructions >= limit)
+        {
+            return Some(HaltReason::InstructionLimit { limit });
+        }
+
+        let time_checked = counters.ticks.is_multiple_of(TIME_CHECK_PERIOD);
+        if let Some(limit) = self
+            .max_time
+            .filter(|&limit| time_checked && started.elapsed() >= limit)
+        {
+            return Some(HaltReason::TimeLimit { limit });
+        }
+
+        None
+    }
+}
+
+/// Program is deterministic. If registers and status repeat at the same PC
+/// while memory, devices and control store were not touched,
+/// the program will repeat them forever
+#[derive(Debug, Default)]
+pub struct LoopDetector {
+    // states are kept whole: equal hash of different states
+    // would stop correct program
+    seen: HashSet<(Registers, Status)>,
+}
+
+impl LoopDetector {
+    /// Anything beyond registers changed: IO, memory or control store write
+    pub fn forget(&mut self) {
+        self.seen.clear();
+    }
+
+    /// Called at instruction boundary. Returns `true` if state was seen before
+    pub fn repeats(&mut self, registers: &Registers, status: Status) -> bool {
+        if self.seen.len() >= MAX_SEEN_STATES {
+            self.forget();
+        }
+
+        !self.seen.insert((registers.clone(), status))
+    }
+}
@@ -0,0 +1,695 @@
+//! Pipelined model of the same ISA. It does not execute microcode:
+//! instructions flow through fetch, decode (operand fetch), execute and
+//! writeback stages, one cycle per stage at best.
+//!
+//! Memory has single port (von Neumann), so memory accesses of different
+//! stages compete for it. Priority: writeback, decode, fetch.
+//!
+//! Accumulator and status are written at the end of writeback. Execute
+//! either takes them from writeback over bypass or waits for one cycle.
+//! Decode waits for stores in flight to the same memory cell.
+//! Branches are resolved in execute, younger instructions are flushed on
+//! misprediction.
+//!
+//! Not modelled: writable control store (WCS and USERn do nothing, as in
+//! hardwired control unit), stores into instructions already fetched
+//! and exit code operand of HALT.
+//!
+//! Tick limit applies to cycles, loop detection is not available.
+
+use std::{collections::HashMap, fmt::Display, time::Instant};
+
+use isa::{CompiledCommand, MemoryItem, Opcode, OperandType, RawAddress};
+
+use crate::{io_controller::IOController, memory::Memory};
+
+use super::{
+    data_path::{ALU_Config, ALU_Output, ALU},
+    status::Status,
+    ExecutionCounters, ExecutionLimits, HaltReason, TRegisterValue,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PredictorKind {
+    /// always predict fall through
+    NotTaken,
+    /// 2-bit saturating counter per branch address
+    Bimodal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineConfig {
+    pub forwarding: bool,
+    pub predictor: PredictorKind,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            forwarding: true,
+            predictor: PredictorKind::Bimodal,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PipelineStats {
+    pub cycles: usize,
+    pub instructions: usize,
+    /// execute waited for accumulator or status
+    pub data_stalls: usize,
+    /// decode waited for store to the cell it reads
+    pub memory_stalls: usize,
+    /// stage waited for memory port
+    pub structural_stalls: usize,
+    /// instructions squashed on misprediction
+    pub flushed: usize,
+    pub forwarded: usize,
+    pub branches: usize,
+    pub mispredicted: usize,
+}
+
+impl PipelineStats {
+    pub fn cycles_per_instruction(&self) -> f64 {
+        self.cycles as f64 / self.instructions.max(1) as f64
+    }
+}
+
+impl Display for PipelineStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Pipelined: {} instructions, {} cycles, CPI {:.2}",
+            self.instructions,
+            self.cycles,
+            self.cycles_per_instruction()
+        )?;
+        writeln!(
+            f,
+            "  stalls: data {}, memory {}, structural {}; forwarded {}",
+            self.data_stalls, self.memory_stalls, self.structural_stalls, self.forwarded
+        )?;
+        write!(
+            f,
+            "  branches: {}, mispredicted {}, flushed {}",
+            self.branches, self.mispredicted, self.flushed
+        )
+    }
+}
+
+struct BranchPredictor {
+    kind: PredictorKind,
+    counters: HashMap<RawAddress, u8>,
+}
+
+impl BranchPredictor {
+    // weakly not taken
+    const INITIAL_COUNTER: u8 = 1;
+
+    fn new(kind: PredictorKind) -> Self {
+        Self {
+            kind,
+            counters: HashMap::new(),
+        }
+    }
+
+    fn predict_taken(&self, address: RawAddress) -> bool {
+        match self.kind {
+            PredictorKind::NotTaken => false,
+            PredictorKind::Bimodal => {
+                self.counters
+                    .get(&address)
+                    .copied()
+                    .unwrap_or(Self::INITIAL_COUNTER)
+                    >= 2
+            }
+        }
+    }
+
+    fn update(&mut self, address: RawAddress, taken: bool) {
+        let counter = self
+            .counters
+            .entry(address)
+            .or_insert(Self::INITIAL_COUNTER);
+        *counter = if taken {
+            (*counter + 1).min(3)
+        } else {
+            counter.saturating_sub(1)
+        };
+    }
+}
+
+/// Result of execute stage to be committed in writeback
+#[derive(Clone, Copy)]
+struct Writeback {
+    accumulator: Option<TRegisterValue>,
+    status: Option<Status>,
+    store: Option<(RawAddress, MemoryItem)>,
+}
+
+/// Instruction in flight
+struct Slot {
+    pc: RawAddress,
+    item: MemoryItem,
+    predicted_next: RawAddress,
+    address: RawAddress,
+    data: MemoryItem,
+    /// memory reads decode still has to perform
+    pending_reads: u8,
+    writeback: Option<Writeback>,
+}
+
+impl Slot {
+    fn command(&self) -> Option<CompiledCommand> {
+        self.item.command()
+    }
+}
+
+impl Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.item {
+            MemoryItem::Command(command) => write!(f, "{:#06x} {:?}", self.pc, command.opcode),
+            MemoryItem::Data(_) => write!(f, "{:#06x} <data>", self.pc),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    DataExecuted {
+        pc: RawAddress,
+    },
+    InstructionAsData {
+        pc: RawAddress,
+    },
+    InputExhausted {
+        pc: RawAddress,
+    },
+    /// execution limit was reached before HALT retired
+    Stopped {
+        reason: Box<HaltReason>,
+        stats: PipelineStats,
+    },
+}
+
+impl std::error::Error for PipelineError {}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::DataExecuted { pc } => {
+                write!(f, "Tried to execute binary data at {pc:#06x}")
+            }
+            PipelineError::InstructionAsData { pc } => {
+                write!(f, "Instruction at {pc:#06x} used instruction as data")
+            }
+            PipelineError::InputExhausted { pc } => {
+                write!(f, "Instruction at {pc:#06x} read from exhausted device")
+            }
+            PipelineError::Stopped { reason, .. } => {
+                write!(f, "Pipelined model stopped: {reason}")
+            }
+        }
+    }
+}
+
+pub struct PipelinedCpu {
+    config: PipelineConfig,
+    memory: Memory,
+    io_controller: IOController,
+    accumulator: TRegisterValue,
+    status: Status,
+    fetch_pc: RawAddress,
+    fetch_enabled: bool,
+    // pipeline latches
+    fetched: Option<Slot>,
+    decoded: Option<Slot>,
+    executed: Option<Slot>,
+    predictor: BranchPredictor,
+    stats: PipelineStats,
+    limits: ExecutionLimits,
+}
+
+fn alu_config(left: TRegisterValue, right: TRegisterValue) -> ALU_Config {
+    ALU_Config {
+        left,
+        right,
+        AND: false,
+        NOT_LEFT: false,
+        NOT_RIGHT: false,
+        INC: false,
+        SHIFT: false,
+        SHIFT_LEFT: false,
+    }
+}
+
+fn reads_accumulator(opcode: Opcode) -> bool {
+    use Opcode::*;
+    matches!(
+        opcode,
+        OUT | STORE | ADD | INC | AND | CMP | SHIFT_LEFT | SHIFT_RIGHT
+    )
+}
+
+fn reads_status(opcode: Opcode) -> bool {
+    use Opcode::*;
+    matches!(opcode, JZC | JZS | JCC | JCS)
+}
+
+impl PipelinedCpu {
+    pub fn new(memory: Memory, io_controller: IOController, config: PipelineConfig) -> Self {
+        Self {
+            config,
+            memory,
+            io_controller,
+            accumulator: 0,
+            status: Status {
+                zero: true,
+                carry: false,
+            },
+            fetch_pc: 0,
+            fetch_enabled: true,
+            fetched: None,
+            decoded: None,
+            executed: None,
+            predictor: BranchPredictor::new(config.predictor),
+            stats: PipelineStats::default(),
+            limits: ExecutionLimits::default(),
+        }
+    }
+
+    /// Checked before every cycle, cycles count as ticks
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+
+        self
+    }
+
+    /// Runs until HALT leaves the pipeline
+    pub fn start(mut self) -> Result<PipelineStats, PipelineError> {
+        let started = Instant::now();
+        loop {
+            let counters = ExecutionCounters {
+                instructions: self.stats.instructions,
+                ticks: self.stats.cycles,
+                stall_ticks: 0,
+            };
+            if let Some(reason) = self.limits.exceeded(counters, started) {
+                log::info!("{}", self.stats);
+                return Err(PipelineError::Stopped {
+                    reason: Box::new(reason),
+                    stats: self.stats,
+                });
+            }
+
+            if !self.cycle()? {
+                break;
+            }
+        }
+
+        log::info!("{}", self.stats);
+
+        Ok(self.stats)
+    }
+
+    /// Returns `false` once HALT is retired
+    fn cycle(&mut self) -> Result<bool, PipelineError> {
+        self.stats.cycles += 1;
+        let mut memory_port_busy = false;
+        let mut redirected = false;
+
+        // writeback: results are committed at the end of cycle
+        let retiring = self.executed.take();
+        let retiring_writeback = retiring.as_ref().and_then(|slot| slot.writeback);
+        if let Some(Writeback {
+            store: Some((address, item)),
+            ..
+        }) = retiring_writeback
+        {
+            self.memory[address] = item;
+            memory_port_busy = true;
+        }
+        let writeback_trace = retiring
+            .as_ref()
+            .map_or("-".to_owned(), |slot| slot.to_string());
+
+        // execute
+        let execute_trace = match self.decoded.take() {
+            Some(slot) if slot.pending_reads == 0 => {
+                let command = slot
+                    .command()
+                    .ok_or(PipelineError::DataExecuted { pc: slot.pc })?;
+
+                let waits_accumulator = reads_accumulator(command.opcode)
+                    && retiring_writeback.is_some_and(|writeback| writeback.accumulator.is_some());
+                let waits_status = reads_status(command.opcode)
+                    && retiring_writeback.is_some_and(|writeback| writeback.status.is_some());
+
+                if (waits_accumulator || waits_status) && !self.config.forwarding {
+                    self.stats.data_stalls += 1;
+                    let trace = format!("{slot} (stall: data)");
+                    self.decoded = Some(slot);
+                    trace
+                } else {
+                    if waits_accumulator || waits_status {
+                        self.stats.forwarded += 1;
+                    }
+
+                    let accumulator = retiring_writeback
+                        .and_then(|writeback| writeback.accumulator)
+                        .unwrap_or(self.accumulator);
+                    let status = retiring_writeback
+                        .and_then(|writeback| writeback.status)
+                        .unwrap_or(self.status);
+
+                    let trace = slot.to_string();
+                    redirected = self.execute(slot, command, accumulator, status)?;
+                    trace
+                }
+            }
+            Some(slot) => {
+                // still waiting for operand
+                self.decoded = Some(slot);
+                "-".to_owned()
+            }
+            None => "-".to_owned(),
+        };
+
+        // commit writeback
+        if let Some(writeback) = retiring_writeback {
+            if let Some(accumulator) = writeback.accumulator {
+                self.accumulator = accumulator;
+            }
+            if let Some(status) = writeback.status {
+                self.status = status;
+            }
+        }
+        if let Some(slot) = &retiring {
+            // HALT is not counted, just as with sequential model
+            if slot.command().map(|command| command.opcode) == Some(Opcode::HALT) {
+                log::info!(
+                    "Cycle {}: F[-] D[-] E[-] W[{}]",
+                    self.stats.cycles,
+                    writeback_trace
+                );
+                return Ok(false);
+            }
+
+            self.stats.instructions += 1;
+        }
+
+        // decode
+        let decode_trace = self.decode(&mut memory_port_busy);
+
+        // fetch
+        let fetch_trace = if !self.fetch_enabled {
+            "-".to_owned()
+        } else if redirected {
+            "(redirect)".to_owned()
+        } else if self.fetched.is_some() {
+            "(stall: busy)".to_owned()
+        } else if memory_port_busy {
+            self.stats.structural_stalls += 1;
+            "(stall: memory port)".to_owned()
+        } else {
+            let slot = self.fetch();
+            let trace = slot.to_string();
+            self.fetched = Some(slot);
+            trace
+        };
+
+        log::info!(
+            "Cycle {}: F[{}] D[{}] E[{}] W[{}]",
+            self.stats.cycles,
+            fetch_trace,
+            decode_trace,
+            execute_trace,
+            writeback_trace
+        );
+
+        Ok(true)
+    }
+
+    fn fetch(&mut self) -> Slot {
+        let pc = self.fetch_pc;
+        let item = self.memory[pc];
+        let fall_through = pc.wrapping_add(1);
+
+        let predicted_next = match item {
+            MemoryItem::Command(command) => {
+                let target = Self::static_target(pc, command);
+                match (command.opcode, target) {
+                    (Opcode::JUMP, Some(target)) => target,
+                    (Opcode::JZC | Opcode::JZS | Opcode::JCC | Opcode::JCS, Some(target))
+                        if self.predictor.predict_taken(pc) =>
+                    {
+                        target
+                    }
+                    (Opcode::HALT, _) => {
+                        // nothing to fetch after HALT
+                        self.fetch_enabled = false;
+                        fall_through
+                    }
+                    _ => fall_through,
+                }
+            }
+            MemoryItem::Data(_) => fall_through,
+        };
+
+        self.fetch_pc = predicted_next;
+
+        Slot {
+            pc,
+            item,
+            predicted_next,
+            address: 0,
+            data: MemoryItem::Data(0),
+            pending_reads: 0,
+            writeback: None,
+        }
+    }
+
+    /// Jump target known right after fetch. Indirect target requires memory access
+    fn static_target(pc: RawAddress, command: CompiledCommand) -> Option<RawAddress> {
+        match command.operand.operand_type {
+            OperandType::Absolute | OperandType::Immediate => Some(command.operand.operand),
+            OperandType::Relative => Some(pc.wrapping_add(1).wrapping_add(command.operand.operand)),
+            OperandType::Indirect | OperandType::None => None,
+        }
+    }
+
+    fn decode(&mut self, memory_port_busy: &mut bool) -> String {
+        let mut slot = match self.decoded.take() {
+            // operand fetch continues
+            Some(slot) if slot.pending_reads > 0 => slot,
+            Some(slot) => {
+                // execute did not take instruction
+                self.decoded = Some(slot);
+                return self
+                    .fetched
+                    .as_ref()
+                    .map_or("-".to_owned(), |slot| format!("{slot} (stall: busy)"));
+            }
+            None => match self.fetched.take() {
+                Some(mut slot) => {
+                    Self::start_decode(&mut slot);
+                    slot
+                }
+                None => return "-".to_owned(),
+            },
+        };
+
+        let trace = if slot.pending_reads == 0 {
+            slot.to_string()
+        } else if *memory_port_busy {
+            self.stats.structural_stalls += 1;
+            format!("{slot} (stall: memory port)")
+        } else if self.store_in_flight(slot.address) {
+            self.stats.memory_stalls += 1;
+            format!("{slot} (stall: memory)")
+        } else {
+            *memory_port_busy = true;
+            Self::read_operand(&mut slot, &self.memory);
+            slot.to_string()
+        };
+
+        self.decoded = Some(slot);
+        trace
+    }
+
+    fn start_decode(slot: &mut Slot) {
+        let Some(command) = slot.command() else {
+            return;
+        };
+
+        let operand = command.operand.operand;
+        let next = slot.pc.wrapping_add(1);
+        (slot.address, slot.pending_reads) = match command.operand.operand_type {
+            OperandType::None => (slot.address, 0),
+            OperandType::Immediate => {
+                slot.data = MemoryItem::Data(operand as u32);
+                (operand, 0)
+            }
+            OperandType::Absolute => (operand, 1),
+            OperandType::Relative => (next.wrapping_add(operand), 1),
+            OperandType::Indirect => (next.wrapping_add(operand), 2),
+        };
+    }
+
+    /// Performs one memory read of operand fetch
+    fn read_operand(slot: &mut Slot, memory: &Memory) {
+        slot.data = memory[slot.address];
+        slot.pending_reads -= 1;
+
+        if slot.pending_reads > 0 {
+            match slot.data {
+                // pointer is read, value it points to is read on next cycle
+                MemoryItem::Data(pointer) => slot.address = pointer as RawAddress,
+                // wrong path may read anything. Execute
+                // reports the error if instruction is on the right path
+                MemoryItem::Command(_) => slot.pending_reads = 0,
+            }
+        }
+    }
+
+    /// Store which is not yet committed to memory
+    fn store_in_flight(&self, address: RawAddress) -> bool {
+        self.executed
+            .as_ref()
+            .and_then(|slot| slot.writeback)
+            .and_then(|writeback| writeback.store)
+            .is_some_and(|(store_address, _)| store_address == address)
+    }
+
+    /// Returns `true` if fetch has to be redirected: HALT or misprediction.
+    /// Redirected fetch starts on the next cycle
+    fn execute(
+        &mut self,
+        mut slot: Slot,
+        command: CompiledCommand,
+        accumulator: TRegisterValue,
+        status: Status,
+    ) -> Result<bool, PipelineError> {
+        use Opcode::*;
+
+        let pc = slot.pc;
+        let data = || {
+            slot.data
+                .data()
+                .ok_or(PipelineError::InstructionAsData { pc })
+        };
+        let arithmetic = |output: ALU_Output| Writeback {
+            accumulator: Some(output.value),
+            status: Some(Status {
+                zero: output.zero,
+                carry: output.carry,
+            }),
+            store: None,
+        };
+        let accumulator_only = |value| Writeback {
+            accumulator: Some(value),
+            status: None,
+            store: None,
+        };
+        let nothing = Writeback {
+            accumulator: None,
+            status: None,
+            store: None,
+        };
+
+        let mut next = slot.pc.wrapping_add(1);
+        if matches!(command.opcode, JZC | JZS | JCC | JCS | JUMP) {
+            self.stats.branches += 1;
+        }
+
+        let writeback = match command.opcode {
+            IN => accumulator_only(
+                self.io_controller
+                    .read(data()? as u8)
+                    .ok_or(PipelineError::InputExhausted { pc })? as u32,
+            ),
+            OUT => {
+                self.io_controller.write(data()? as u8, accumulator as u8);
+                nothing
+            }
+            LOAD => accumulator_only(data()?),
+            STORE => Writeback {
+                store: Some((slot.address, MemoryItem::Data(accumulator))),
+                ..nothing
+            },
+            ADD => arithmetic(ALU(alu_config(accumulator, data()?))),
+            INC => arithmetic(ALU(ALU_Config {
+                INC: true,
+                ..alu_config(accumulator, 0)
+            })),
+            AND => arithmetic(ALU(ALU_Config {
+                AND: true,
+                ..alu_config(accumulator, data()?)
+            })),
+            CMP => Writeback {
+                accumulator: None,
+                ..arithmetic(ALU(ALU_Config {
+                    NOT_RIGHT: true,
+                    INC: true,
+                    ..alu_config(accumulator, data()?)
+                }))
+            },
+            SHIFT_LEFT | SHIFT_RIGHT => accumulator_only(
+                ALU(ALU_Config {
+                    SHIFT: true,
+                    SHIFT_LEFT: command.opcode == SHIFT_LEFT,
+                    ..alu_config(accumulator, 0)
+                })
+                .value,
+            ),
+            JZC | JZS | JCC | JCS | JUMP => {
+                let taken = match command.opcode {
+                    JZC => !status.zero,
+                    JZS => status.zero,
+                    JCC => !status.carry,
+                    JCS => status.carry,
+                    _ => true,
+                };
+                if command.opcode != JUMP {
+                    self.predictor.update(slot.pc, taken);
+                }
+                if taken {
+                    next = slot.address;
+                }
+                nothing
+            }
+            TAS => Writeback {
+                store: Some((slot.address, MemoryItem::Data(1))),
+                ..arithmetic(ALU(alu_config(0, data()?)))
+            },
+            // pipelined model has single core
+            COREID => accumulator_only(0),
+            NOP | HALT | WCS | USER(_) => nothing,
+        };
+
+        let redirected = if command.opcode == HALT {
+            self.fetch_enabled = false;
+            self.flush();
+            true
+        } else if next != slot.predicted_next {
+            self.stats.mispredicted += 1;
+            self.flush();
+            self.fetch_pc = next;
+            self.fetch_enabled = true;
+            true
+        } else {
+            false
+        };
+
+        slot.writeback = Some(writeback);
+        self.executed = Some(slot);
+
+        Ok(redirected)
+    }
+
+    /// Squashes instructions younger than the one in execute
+    fn flush(&mut self) {
+        self.stats.flushed += self.fetched.take().is_some() as usize;
+        self.stats.flushed += self.decoded.take().is_some() as usize;
+    }
+}
@@ -0,0 +1,116 @@
+//! Outcome of a run: why CPU stopped and what state it stopped in
+
+use std::{fmt::Display, time::Duration};
+
+use isa::RawAddress;
+
+use crate::cache::CacheStats;
+
+use serde::Serialize;
+
+use super::{CpuFault, ExecutionCounters, Registers, Status};
+
+/// Largest HALT operand. Process exit statuses above belong to simulator
+pub const MAX_EXIT_CODE: u32 = 127;
+/// Process exit status when simulator itself fails, e.g. on invalid arguments
+pub const ERROR_EXIT_CODE: i32 = 128;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum HaltReason {
+    /// HALT instruction executed. Exit code is its operand, 0 when omitted.
+    /// Operand above `MAX_EXIT_CODE` faults instead
+    Halt {
+        exit_code: u32,
+    },
+    Fault(CpuFault),
+    /// IN requested data from device which has nothing left
+    InputExhausted,
+    TickLimit {
+        limit: usize,
+    },
+    InstructionLimit {
+        limit: usize,
+    },
+    TimeLimit {
+        limit: Duration,
+    },
+    /// architectural state repeated without IO or memory writes in between
+    InfiniteLoop {
+        program_counter: RawAddress,
+    },
+}
+
+impl HaltReason {
+    /// Process exit status which corresponds to halt reason.
+    /// Statuses of simulator follow `ERROR_EXIT_CODE`, so they
+    /// never collide with exit code of program
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HaltReason::Halt { exit_code } => *exit_code as i32,
+            HaltReason::InputExhausted => ERROR_EXIT_CODE + 1,
+            HaltReason::Fault(_) => ERROR_EXIT_CODE + 2,
+            HaltReason::TickLimit { .. }
+            | HaltReason::InstructionLimit { .. }
+            | HaltReason::TimeLimit { .. } => ERROR_EXIT_CODE + 3,
+            HaltReason::InfiniteLoop { .. } => ERROR_EXIT_CODE + 4,
+        }
+    }
+}
+
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::Halt { exit_code } => write!(f, "HALT with exit code {exit_code}"),
+            HaltReason::InputExhausted => write!(f, "input exhausted"),
+            HaltReason::Fault(fault) => write!(f, "fault, {}", fault.cause),
+            HaltReason::TickLimit { limit } => write!(f, "tick limit of {limit} reached"),
+            HaltReason::InstructionLimit { limit } => {
+                write!(f, "instruction limit of {limit} reached")
+            }
+            HaltReason::TimeLimit { limit } => {
+                write!(f, "time limit of {:.3}s reached", limit.as_secs_f64())
+            }
+            HaltReason::InfiniteLoop { program_counter } => {
+                write!(f, "infinite loop at {program_counter:#06x}")
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RunReport {
+    pub halt_reason: HaltReason,
+    pub counters: ExecutionCounters,
+    pub registers: Registers,
+    pub status: Status,
+    /// bytes written to devices, in order of writes
+    pub output: Vec<u8>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub caches: Vec<CacheStats>,
+}
+
+impl RunReport {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl Display for RunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Stopped: {}", self.halt_reason)?;
+        writeln!(
+            f,
+            "Instructions: {}; MC: {}",
+            self.counters.instructions, self.counters.ticks
+        )?;
+        writeln!(f, "Registers:")?;
+        write!(f, "{}", self.registers)?;
+        writeln!(f, "Status: {}", self.status)?;
+        write!(f, "Output: {:?}", String::from_utf8_lossy(&self.output))?;
+        for stats in &self.caches {
+            write!(f, "\n{stats}")?;
+        }
+
+        Ok(())
+    }
+}
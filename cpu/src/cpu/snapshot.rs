@@ -0,0 +1,107 @@
+//! Full machine state which can be saved to a file and restored later.
+//! Configuration (control unit kind, connected devices, limits) is not
+//! part of snapshot: machine has to be built the same way before restore
+
+use std::{error::Error, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::CacheHierarchy,
+    io_controller::{DeviceStateError, IOControllerState},
+    memory::MemoryState,
+};
+
+use super::{
+    control_unit::{ControlUnitState, MicrocodeError},
+    Cpu, ExecutionCounters, Registers, Status,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub registers: Registers,
+    pub status: Status,
+    pub control_unit: ControlUnitState,
+    pub counters: ExecutionCounters,
+    pub memory: MemoryState,
+    pub devices: IOControllerState,
+    /// tags and statistics. Restored only into CPU configured with caches
+    #[serde(default)]
+    pub caches: Option<CacheHierarchy>,
+    /// microinstruction is held until memory is ready
+    #[serde(default)]
+    pub memory_wait: Option<usize>,
+    #[serde(default)]
+    pub fetching: bool,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    ControlUnit(MicrocodeError),
+    Device(DeviceStateError),
+}
+
+impl Error for SnapshotError {}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::ControlUnit(error) => {
+                writeln!(f, "Could not restore control unit: {error}")
+            }
+            SnapshotError::Device(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+impl Cpu {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers.clone(),
+            status: self.status,
+            control_unit: self.control_unit.state(),
+            counters: self.counters(),
+            memory: self.memory.state(),
+            devices: self.io_controller.state(),
+            caches: self.caches.clone(),
+            memory_wait: self.memory_wait,
+            fetching: self.fetching,
+        }
+    }
+
+    /// Restored CPU is able to continue: halt reason is cleared.
+    /// Failed restore may leave CPU partially restored
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), SnapshotError> {
+        self.control_unit
+            .restore(snapshot.control_unit)
+            .map_err(SnapshotError::ControlUnit)?;
+        self.io_controller
+            .restore(snapshot.devices)
+            .map_err(SnapshotError::Device)?;
+        self.memory.restore(snapshot.memory);
+        self.registers = snapshot.registers;
+        self.status = snapshot.status;
+        self.instructions_executed = snapshot.counters.instructions;
+        self.micro_instructions_executed = snapshot.counters.ticks;
+        self.stall_ticks = snapshot.counters.stall_ticks;
+
+        if let (Some(caches), Some(restored)) = (&mut self.caches, snapshot.caches) {
+            *caches = restored;
+        }
+        self.memory_wait = snapshot.memory_wait;
+        self.fetching = snapshot.fetching;
+        self.halt_reason = None;
+        self.recent_instructions.clear();
+        if let Some(loop_detector) = &mut self.loop_detector {
+            loop_detector.forget();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Static cost of every opcode and operand type combination.
+//! Costs are derived by walking control store the same way control unit
+//! does, with command register fixed to the combination of interest.
+
+use std::fmt::Write;
+
+use isa::{CompiledCommand, Opcode, Operand, OperandType};
+use serde::Serialize;
+
+use super::control_unit::{user_routine_position, ControlUnit, Signal};
+
+// guards against routines which never return to fetch
+const MAX_TICKS: usize = 1000;
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct TickCost {
+    pub opcode: Opcode,
+    pub operand_type: OperandType,
+    pub fetch: usize,
+    pub operand_fetch: usize,
+    pub execution: usize,
+    pub total: usize,
+    /// program counter is written depending on status flags.
+    /// Cost is the same for both outcomes, effect is not
+    pub conditional: bool,
+    /// instruction stops the CPU. HALT tick itself is not counted
+    pub halts: bool,
+    /// routine lies in writable control store, cost reflects its current content
+    pub writable: bool,
+    /// routine did not return to fetch within `MAX_TICKS`
+    pub unbounded: bool,
+}
+
+impl TickCost {
+    fn marks(&self) -> String {
+        let mut marks = String::new();
+        for (flag, mark) in [
+            (self.conditional, '*'),
+            (self.halts, 'H'),
+            (self.writable, 'W'),
+            (self.unbounded, '!'),
+        ] {
+            if flag {
+                marks.push(mark);
+            }
+        }
+
+        marks
+    }
+
+    fn cell(&self) -> String {
+        format!("{}{}", self.total, self.marks())
+    }
+}
+
+#[derive(Serialize)]
+pub struct TickCostTable {
+    pub entries: Vec<TickCost>,
+}
+
+const LEGEND: &str = "* conditional: program counter write depends on status flags\n\
+                      H halts: HALT tick itself is not counted\n\
+                      W routine lies in writable control store\n\
+                      ! routine does not return to fetch";
+
+impl TickCostTable {
+    pub fn new(control_unit: ControlUnit) -> Self {
+        let mut entries = Vec::new();
+        for opcode in Opcode::ALL {
+            for operand_type in OperandType::ALL {
+                entries.push(walk(&mut Self::reset(&control_unit), opcode, operand_type));
+            }
+        }
+
+        Self { entries }
+    }
+
+    // every walk starts from instruction fetch
+    fn reset(control_unit: &ControlUnit) -> ControlUnit {
+        match control_unit {
+            ControlUnit::Microprogrammed { microcode, .. } => ControlUnit::Microprogrammed {
+                microcode: microcode.clone(),
+                microcode_program_counter: 0,
+            },
+            ControlUnit::Hardwired(_) => ControlUnit::hardwired(),
+        }
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[TickCost]> {
+        self.entries.chunks(OperandType::ALL.len())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        let _ = write!(output, "{:<12}", "opcode");
+        for operand_type in OperandType::ALL {
+            let _ = write!(output, "{:>11}", format!("{operand_type:?}"));
+        }
+        output.push('\n');
+
+        for row in self.rows() {
+            let _ = write!(output, "{:<12}", row[0].opcode.to_string());
+            for cost in row {
+                let _ = write!(output, "{:>11}", cost.cell());
+            }
+            output.push('\n');
+        }
+
+        output.push('\n');
+        output.push_str(LEGEND);
+        output.push('\n');
+
+        output
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut output = String::from("| opcode |");
+        for operand_type in OperandType::ALL {
+            let _ = write!(output, " {operand_type:?} |");
+        }
+        output.push_str("\n| --- |");
+        output.push_str(&" ---: |".repeat(OperandType::ALL.len()));
+        output.push('\n');
+
+        for row in self.rows() {
+            let _ = write!(output, "| `{}` |", row[0].opcode);
+            for cost in row {
+                let _ = write!(output, " {} |", cost.cell());
+            }
+            output.push('\n');
+        }
+
+        output.push('\n');
+        for line in LEGEND.lines() {
+            let _ = writeln!(output, "- `{}", line.replacen(' ', "` ", 1));
+        }
+
+        output
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn walk(control_unit: &mut ControlUnit, opcode: Opcode, operand_type: OperandType) -> TickCost {
+    let command = CompiledCommand {
+        opcode,
+        operand: Operand {
+            operand: 0,
+            operand_type,
+        },
+    };
+
+    let mut cost = TickCost {
+        opcode,
+        operand_type,
+        fetch: 0,
+        operand_fetch: 0,
+        execution: 0,
+        total: 0,
+        conditional: false,
+        halts: false,
+        writable: false,
+        unbounded: false,
+    };
+
+    // 0 - fetch, 1 - operand fetch, 2 - execution
+    let mut phase = 0;
+    loop {
+        if cost.total >= MAX_TICKS {
+            cost.unbounded = true;
+            break;
+        }
+
+        let micro_instruction = control_unit.microinstruction(&command);
+        if micro_instruction.contains(&Signal::HALT) {
+            cost.halts = true;
+            break;
+        }
+
+        let microcode_program_counter = control_unit.microcode_program_counter();
+        cost.writable |= microcode_program_counter
+            .and_then(user_routine_position)
+            .is_some();
+        cost.conditional |= micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_Z)
+            || micro_instruction.contains(&Signal::WRITE_PROGRAM_COUNTER_C);
+
+        match phase {
+            0 => cost.fetch += 1,
+            1 => cost.operand_fetch += 1,
+            _ => cost.execution += 1,
+        }
+        cost.total += 1;
+
+        match (
+            micro_instruction.contains(&Signal::SELECT_MC_1),
+            micro_instruction.contains(&Signal::SELECT_MC_0),
+        ) {
+            (true, false) => phase = 1,
+            (true, true) => phase = 2,
+            _ => (),
+        }
+
+        control_unit.advance(&micro_instruction, &command);
+        if control_unit.is_fetching_instruction() {
+            break;
+        }
+    }
+
+    cost
+}
@@ -0,0 +1,590 @@
+//! Debug Adapter Protocol server over stdio, so editors can debug assembler
+//! source. Launch runs `assembler` executable on source, breakpoints are set
+//! by source line and every step executes one instruction.
+//! Requests are read on separate thread, so running program can be paused.
+
+use std::{
+    collections::VecDeque,
+    env,
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use isa::{CompiledProgram, DebugInfo, MemoryItem};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    cpu::{format_microinstruction, ControlUnit, Cpu, ExecutionLimits, HaltReason},
+    debugger::{Breakpoint, Debugger, Register, Stop},
+    io_controller::{IOController, SimpleInputOutput},
+    memory::Memory,
+    Simulator,
+};
+
+/// CPU is the only thread
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+/// ticks between checks whether client asks to pause
+const PAUSE_CHECK_PERIOD: usize = 1024;
+
+#[derive(Debug)]
+pub enum DapError {
+    NotLaunched,
+    MissingArgument {
+        argument: String,
+    },
+    InvalidArgument {
+        argument: String,
+        value: String,
+    },
+    /// assembler failed or produced unusable output
+    Launch {
+        message: String,
+    },
+    UnknownExpression {
+        expression: String,
+    },
+    UnsupportedRequest {
+        command: String,
+    },
+    Io(io::Error),
+}
+
+impl Error for DapError {}
+
+impl Display for DapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DapError::NotLaunched => writeln!(f, "Program is not launched"),
+            DapError::MissingArgument { argument } => {
+                writeln!(f, "Launch argument `{argument}` is required")
+            }
+            DapError::InvalidArgument { argument, value } => {
+                writeln!(f, "Invalid value `{value}` of launch argument `{argument}`")
+            }
+            DapError::Launch { message } => writeln!(f, "Launch failed: {message}"),
+            DapError::UnknownExpression { expression } => {
+                writeln!(
+                    f,
+                    "`{expression}` is neither register nor label nor address"
+                )
+            }
+            DapError::UnsupportedRequest { command } => {
+                writeln!(f, "Request `{command}` is not supported")
+            }
+            DapError::Io(error) => writeln!(f, "{error}"),
+        }
+    }
+}
+
+impl From<io::Error> for DapError {
+    fn from(error: io::Error) -> Self {
+        DapError::Io(error)
+    }
+}
+
+struct Session {
+    debugger: Debugger,
+    source: PathBuf,
+    // ids of breakpoints set from source lines
+    breakpoints: Vec<usize>,
+    // bytes of device output already sent to client
+    forwarded: usize,
+    stop_on_entry: bool,
+}
+
+pub struct DapServer<W: Write> {
+    output: W,
+    requests: Receiver<Value>,
+    // requests which arrived while program was running
+    pending: VecDeque<Value>,
+    sequence: u64,
+    session: Option<Session>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(input: impl Read + Send + 'static, output: W) -> Self {
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            output,
+            requests,
+            pending: VecDeque::new(),
+            sequence: 0,
+            session: None,
+        }
+    }
+
+    /// Serves requests until client disconnects or input ends
+    pub fn serve(&mut self) -> io::Result<()> {
+        loop {
+            let request = match self.pending.pop_front() {
+                Some(request) => request,
+                None => match self.requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            if !self.handle(&request)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns `false` once client disconnects
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "cpu" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [{
+                    "name": "Registers",
+                    "variablesReference": REGISTERS_REFERENCE,
+                    "expensive": false,
+                }]
+            })),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => self
+                .session()
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "configurationDone" | "next" | "stepIn" | "stepOut" | "pause" => {
+                self.session().map(|_| Value::Null)
+            }
+            "disconnect" | "terminate" => Ok(Value::Null),
+            _ => Err(DapError::UnsupportedRequest {
+                command: command.to_owned(),
+            }),
+        };
+        let success = body.is_ok();
+        self.respond(request, body)?;
+
+        match command {
+            "launch" if success => self.event("initialized", Value::Null)?,
+            "configurationDone" if success => {
+                if self
+                    .session
+                    .as_ref()
+                    .is_some_and(|session| session.stop_on_entry)
+                {
+                    self.stopped("entry")?;
+                } else {
+                    self.resume(false)?;
+                }
+            }
+            "continue" if success => self.resume(false)?,
+            // no calls in this ISA: every step is one instruction
+            "next" | "stepIn" | "stepOut" if success => self.resume(true)?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => (),
+        }
+
+        Ok(true)
+    }
+
+    fn session(&self) -> Result<&Session, DapError> {
+        self.session.as_ref().ok_or(DapError::NotLaunched)
+    }
+
+    /// Arguments: `program` - assembler source, `input` - file fed to port 0,
+    /// `assembler` - its executable, next to this one by default,
+    /// `controlUnit` - `microcode` or `hardwired`, `maxTicks`, `stopOnEntry`
+    fn launch(&mut self, arguments: &Value) -> Result<Value, DapError> {
+        let source = arguments["program"]
+            .as_str()
+            .map(PathBuf::from)
+            .ok_or_else(|| DapError::MissingArgument {
+                argument: "program".into(),
+            })?;
+        let input = match arguments["input"].as_str() {
+            Some(path) => fs::read_to_string(path)?,
+            None => String::new(),
+        };
+        let assembler = match arguments["assembler"].as_str() {
+            Some(path) => PathBuf::from(path),
+            None => {
+                env::current_exe()?.with_file_name(format!("assembler{}", env::consts::EXE_SUFFIX))
+            }
+        };
+        let control_unit = match arguments["controlUnit"].as_str() {
+            None | Some("microcode") => ControlUnit::microprogrammed(),
+            Some("hardwired") => ControlUnit::hardwired(),
+            Some(other) => {
+                return Err(DapError::InvalidArgument {
+                    argument: "controlUnit".into(),
+                    value: other.into(),
+                })
+            }
+        };
+        let limits = ExecutionLimits {
+            max_ticks: arguments["maxTicks"].as_u64().map(|ticks| ticks as usize),
+            ..ExecutionLimits::default()
+        };
+
+        let (program, debug_info) = assemble(&assembler, &source)?;
+        let memory = Memory::burn(program).map_err(|error| DapError::Launch {
+            message: error.to_string(),
+        })?;
+        let cpu = Cpu::new(
+            memory,
+            IOController::new().connect(0, Box::new(SimpleInputOutput::new(input).silent())),
+        )
+        .without_tick_log()
+        .with_control_unit(control_unit)
+        .with_limits(limits);
+
+        self.session = Some(Session {
+            debugger: Debugger::new(Simulator::new(cpu), debug_info),
+            source,
+            breakpoints: Vec::new(),
+            forwarded: 0,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or_default(),
+        });
+
+        Ok(Value::Null)
+    }
+
+    /// Replaces breakpoints of source. Breakpoint on line without
+    /// instruction moves to the next line which has one
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, DapError> {
+        let session = self.session.as_mut().ok_or(DapError::NotLaunched)?;
+        for id in session.breakpoints.drain(..) {
+            session.debugger.remove_point(id);
+        }
+
+        let same_source = arguments["source"]["path"]
+            .as_str()
+            .is_some_and(|path| same_file(Path::new(path), &session.source));
+        let lines = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64());
+
+        let mut breakpoints = Vec::new();
+        for line in lines {
+            let instruction = same_source
+                .then(|| {
+                    session
+                        .debugger
+                        .debug_info()
+                        .instruction_at_line(line as usize)
+                })
+                .flatten();
+
+            breakpoints.push(match instruction {
+                Some((line, address)) => {
+                    let id = session.debugger.add_breakpoint(Breakpoint {
+                        address: Some(address),
+                        condition: None,
+                    });
+                    session.breakpoints.push(id);
+                    json!({ "id": id, "verified": true, "line": line })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instruction at or after this line",
+                }),
+            });
+        }
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Single frame: instruction being executed
+    fn stack_trace(&self) -> Result<Value, DapError> {
+        let session = self.session()?;
+        let debug_info = session.debugger.debug_info();
+        let address = session.debugger.current_instruction();
+
+        let name = match debug_info.nearest_label(address) {
+            Some((label, 0)) => label.to_owned(),
+            Some((label, offset)) => format!("{label}+{offset}"),
+            None => format!("{address:#06x}"),
+        };
+        let mut frame = json!({
+            "id": 1,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("{address:#06x}"),
+        });
+        if let Some(line) = debug_info.line_of(address) {
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+            frame["source"] = json!({
+                "name": session.source.file_name().map(|name| name.to_string_lossy()),
+                "path": session.source,
+            });
+        }
+
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, DapError> {
+        let cpu = self.session()?.debugger.simulator().cpu();
+        if arguments["variablesReference"].as_u64() != Some(REGISTERS_REFERENCE) {
+            return Ok(json!({ "variables": [] }));
+        }
+
+        let variable = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let mut variables: Vec<Value> = Register::ALL
+            .iter()
+            .map(|&register| {
+                let value = register.value(cpu).map_or_else(
+                    || cpu.registers().data.to_string(),
+                    |value| value.to_string(),
+                );
+                variable(&register.to_string(), value)
+            })
+            .collect();
+        let counters = cpu.counters();
+        variables.extend([
+            variable("cr", cpu.registers().command.to_string()),
+            variable(
+                "signals",
+                format_microinstruction(&cpu.current_microinstruction()),
+            ),
+            variable("ticks", counters.ticks.to_string()),
+            variable("instructions", counters.instructions.to_string()),
+        ]);
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Register, or memory cell at label, `label+offset` or address
+    fn evaluate(&self, arguments: &Value) -> Result<Value, DapError> {
+        let debugger = &self.session()?.debugger;
+        let expression = arguments["expression"].as_str().unwrap_or_default().trim();
+        let cpu = debugger.simulator().cpu();
+
+        let result = match Register::parse(expression) {
+            Some(register) => register.value(cpu).map_or_else(
+                || cpu.registers().data.to_string(),
+                |value| value.to_string(),
+            ),
+            None => {
+                let address = debugger.parse_location(expression).map_err(|_| {
+                    DapError::UnknownExpression {
+                        expression: expression.to_owned(),
+                    }
+                })?;
+                match cpu.memory()[address] {
+                    MemoryItem::Data(value) => value.to_string(),
+                    MemoryItem::Command(_) => debugger.disassemble(address),
+                }
+            }
+        };
+
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    /// Runs until breakpoint, end of step, pause request or CPU stop
+    fn resume(&mut self, step: bool) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let requests = &self.requests;
+        let pending = &mut self.pending;
+
+        let mut paused = false;
+        let stop = session.debugger.run(|simulator| {
+            if step && simulator.cpu().control_unit().is_fetching_instruction() {
+                return true;
+            }
+
+            if simulator.counters().ticks % PAUSE_CHECK_PERIOD == 0 {
+                for request in requests.try_iter() {
+                    let command = request["command"].as_str().unwrap_or_default();
+                    paused |= matches!(command, "pause" | "disconnect" | "terminate");
+                    pending.push_back(request);
+                }
+            }
+            paused
+        });
+        self.forward_output()?;
+
+        match stop {
+            Stop::Stepped if paused => self.stopped("pause"),
+            Stop::Stepped => self.stopped("step"),
+            Stop::Breakpoint { .. } => self.stopped("breakpoint"),
+            Stop::Watchpoint { .. } => self.stopped("data breakpoint"),
+            Stop::Halted => self.exited(),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn exited(&mut self) -> io::Result<()> {
+        let Some(halt_reason) = self
+            .session
+            .as_ref()
+            .and_then(|session| session.debugger.simulator().halt_reason())
+            .cloned()
+        else {
+            return Ok(());
+        };
+
+        if !matches!(halt_reason, HaltReason::Halt { .. }) {
+            let output = format!("Stopped: {halt_reason}\n");
+            self.event("output", json!({ "category": "console", "output": output }))?;
+        }
+        self.event("exited", json!({ "exitCode": halt_reason.exit_code() }))?;
+        self.event("terminated", Value::Null)
+    }
+
+    /// Sends what program wrote to devices since last time
+    fn forward_output(&mut self) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let written = session.debugger.simulator().cpu().io_controller().written();
+        if written.len() == session.forwarded {
+            return Ok(());
+        }
+
+        let output = String::from_utf8_lossy(&written[session.forwarded..]).into_owned();
+        session.forwarded = written.len();
+
+        self.event("output", json!({ "category": "stdout", "output": output }))
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, DapError>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(Value::Null) => (),
+            Ok(body) => response["body"] = body,
+            Err(error) => response["message"] = json!(error.to_string().trim_end()),
+        }
+
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.sequence += 1;
+        message["seq"] = json!(self.sequence);
+
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )?;
+        self.output.flush()
+    }
+}
+
+/// Message framed with `Content-Length` header. `None` at end of input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        match header.trim() {
+            "" if length.is_some() => break,
+            header => {
+                if let Some(value) = header.strip_prefix("Content-Length:") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+    }
+
+    let mut content = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut content)?;
+
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+/// Runs assembler on source. Returns program along with its debug info
+fn assemble(assembler: &Path, source: &Path) -> Result<(CompiledProgram, DebugInfo), DapError> {
+    let directory = env::temp_dir();
+    let name = format!("csa-dap-{}", process::id());
+    let program_path = directory.join(format!("{name}.json"));
+    let debug_info_path = directory.join(format!("{name}.debug.json"));
+
+    let output = Command::new(assembler)
+        .arg(source)
+        .arg(&program_path)
+        .arg("--debug-info")
+        .arg(&debug_info_path)
+        .output()
+        .map_err(|error| DapError::Launch {
+            message: format!("can not run {}: {error}", assembler.display()),
+        })?;
+    // assembler reports errors on stderr
+    if !output.status.success() || !output.stderr.is_empty() {
+        return Err(DapError::Launch {
+            message: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+
+    let program = read_json(&program_path);
+    let debug_info = read_json(&debug_info_path);
+    let _ = fs::remove_file(program_path);
+    let _ = fs::remove_file(debug_info_path);
+
+    Ok((program?, debug_info?))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, DapError> {
+    serde_json::from_reader(File::open(path)?).map_err(|error| DapError::Launch {
+        message: error.to_string(),
+    })
+}
+
+fn same_file(left: &Path, right: &Path) -> bool {
+    match (left.canonicalize(), right.canonicalize()) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => left == right,
+    }
+}
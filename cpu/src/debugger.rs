@@ -0,0 +1,920 @@
+//! Interactive debugger on top of `Simulator`. Reads commands line by line,
+//! runs machine until breakpoint, watchpoint or stop and prints its state.
+//! Labels come from debug info written by assembler with `--debug-info`.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    io::{self, BufRead, Write},
+};
+
+use isa::{CompiledCommand, DebugInfo, MemoryItem, OperandType, RawAddress, RawPort};
+
+use crate::{
+    cpu::{format_microinstruction, Cpu, HaltReason, Signal},
+    Simulator,
+};
+
+const PROMPT: &str = "(cpu) ";
+/// cells `disassemble` shows before and after PC
+const DISASSEMBLY_CONTEXT: RawAddress = 4;
+
+const HELP: &str = "\
+break <location> [if <condition>]  stop before instruction at location is fetched
+break if <condition>               stop before any instruction when condition holds
+watch <location> [read|write|access]     stop after memory cell is accessed, write by default
+watch port <port> [read|write|access]    stop after device is accessed, any access by default
+delete [id]                        remove breakpoint or watchpoint, all without id
+info                               list breakpoints and watchpoints
+tick [n]                           execute n ticks
+micro [n]                          execute n microinstructions, skipping memory wait states
+step [n]                           execute n instructions
+continue                           run until breakpoint, watchpoint or stop
+reverse-tick [n]                   undo n ticks
+reverse-step [n]                   go back to start of n-th previous instruction
+last-write <location>              go back to the tick which last wrote memory cell
+registers                          show registers, status and signals of current microinstruction
+disassemble [location] [count]     show memory, around PC by default
+quit                               leave debugger
+Location is address, decimal or 0x hex, label or label+offset.
+Condition is <register> <==|!=|<|<=|>|>=> <value>, registers are acc, pc, ar, dr, z, c.
+Empty line repeats previous command.
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Accumulator,
+    ProgramCounter,
+    Address,
+    Data,
+    Zero,
+    Carry,
+}
+
+impl Register {
+    pub const ALL: [Register; 6] = [
+        Register::Accumulator,
+        Register::ProgramCounter,
+        Register::Address,
+        Register::Data,
+        Register::Zero,
+        Register::Carry,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "acc" | "accumulator" => Register::Accumulator,
+            "pc" => Register::ProgramCounter,
+            "ar" | "address" => Register::Address,
+            "dr" | "data" => Register::Data,
+            "z" | "zero" => Register::Zero,
+            "c" | "carry" => Register::Carry,
+            _ => return None,
+        })
+    }
+
+    /// `None` when data register holds an instruction
+    pub fn value(self, cpu: &Cpu) -> Option<u32> {
+        let registers = cpu.registers();
+        match self {
+            Register::Accumulator => Some(registers.accumulator),
+            Register::ProgramCounter => Some(registers.program_counter as u32),
+            Register::Address => Some(registers.address as u32),
+            Register::Data => registers.data.data(),
+            Register::Zero => Some(cpu.status().zero as u32),
+            Register::Carry => Some(cpu.status().carry as u32),
+        }
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Register::Accumulator => "acc",
+            Register::ProgramCounter => "pc",
+            Register::Address => "ar",
+            Register::Data => "dr",
+            Register::Zero => "z",
+            Register::Carry => "c",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    // two-character operators go first, so `<=` is not taken for `<`
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    pub fn holds(self, left: u32, right: u32) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (operator, _) = Comparison::OPERATORS
+            .iter()
+            .find(|(_, comparison)| comparison == self)
+            .expect("every comparison has operator");
+        write!(f, "{operator}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u32,
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &Cpu) -> bool {
+        self.register
+            .value(cpu)
+            .is_some_and(|value| self.comparison.holds(value, self.value))
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.register, self.comparison, self.value)
+    }
+}
+
+/// Checked before every instruction fetch. Without address
+/// stops before any instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: Option<RawAddress>,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn is_hit(&self, cpu: &Cpu) -> bool {
+        cpu.control_unit().is_fetching_instruction()
+            && self
+                .address
+                .is_none_or(|address| address == cpu.registers().program_counter)
+            && self.condition.is_none_or(|condition| condition.holds(cpu))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// read or write
+    Any,
+}
+
+impl Access {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "read" => Access::Read,
+            "write" => Access::Write,
+            "access" => Access::Any,
+            _ => return None,
+        })
+    }
+
+    fn covers(self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::Any => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchTarget {
+    /// instruction fetch reads memory too
+    Memory(RawAddress),
+    Port(RawPort),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Point {
+    Break(Breakpoint),
+    Watch(Watchpoint),
+}
+
+/// Why execution returned to user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// requested amount of steps is done
+    Stepped,
+    Breakpoint {
+        id: usize,
+    },
+    Watchpoint {
+        id: usize,
+        write: bool,
+    },
+    /// CPU stopped, see `Simulator::halt_reason`
+    Halted,
+}
+
+#[derive(Debug)]
+pub enum DebuggerError {
+    UnknownCommand { command: String },
+    InvalidArgument { argument: String },
+    UnknownLabel { label: String },
+    UnknownPoint { id: usize },
+}
+
+impl Error for DebuggerError {}
+
+impl Display for DebuggerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DebuggerError::UnknownCommand { command } => {
+                writeln!(f, "Unknown command `{command}`, see `help`")
+            }
+            DebuggerError::InvalidArgument { argument } => {
+                writeln!(f, "Invalid argument `{argument}`, see `help`")
+            }
+            DebuggerError::UnknownLabel { label } => writeln!(f, "No label `{label}`"),
+            DebuggerError::UnknownPoint { id } => {
+                writeln!(f, "No breakpoint or watchpoint {id}")
+            }
+        }
+    }
+}
+
+pub struct Debugger {
+    simulator: Simulator,
+    debug_info: DebugInfo,
+    points: BTreeMap<usize, Point>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new(simulator: Simulator, debug_info: DebugInfo) -> Self {
+        Self {
+            simulator,
+            debug_info,
+            points: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn simulator_mut(&mut self) -> &mut Simulator {
+        &mut self.simulator
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    /// Returns id of new breakpoint
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.add_point(Point::Break(breakpoint))
+    }
+
+    /// Returns id of new watchpoint
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.add_point(Point::Watch(watchpoint))
+    }
+
+    fn add_point(&mut self, point: Point) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.points.insert(id, point);
+
+        id
+    }
+
+    /// Removes breakpoint or watchpoint. Returns `false` if there is none with `id`
+    pub fn remove_point(&mut self, id: usize) -> bool {
+        self.points.remove(&id).is_some()
+    }
+
+    /// Reads commands until `quit` or end of input
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut previous = String::new();
+
+        write!(output, "{PROMPT}")?;
+        output.flush()?;
+        for line in input.lines() {
+            let mut line = line?;
+            if line.trim().is_empty() {
+                line = previous;
+            }
+
+            if !self.execute(&line, &mut output)? {
+                return Ok(());
+            }
+            previous = line;
+
+            write!(output, "{PROMPT}")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes single command. Returns `false` on `quit`
+    pub fn execute(&mut self, line: &str, output: &mut impl Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        let result = match command {
+            "quit" | "q" => return Ok(false),
+            "help" | "h" => {
+                write!(output, "{HELP}")?;
+                Ok(())
+            }
+            "break" | "b" => self.command_break(&arguments, output),
+            "watch" | "w" => self.command_watch(&arguments, output),
+            "delete" => self.command_delete(&arguments, output),
+            "info" | "i" => self.command_info(output),
+            "tick" | "t" => self.command_run(&arguments, output, |_| true),
+            "micro" | "m" => {
+                let mut stall_ticks = self.simulator.counters().stall_ticks;
+                self.command_run(&arguments, output, move |simulator| {
+                    // tick spent waiting for memory does not execute microinstruction
+                    let waited = simulator.counters().stall_ticks != stall_ticks;
+                    stall_ticks = simulator.counters().stall_ticks;
+                    !waited
+                })
+            }
+            "step" | "s" => self.command_run(&arguments, output, |simulator| {
+                simulator.cpu().control_unit().is_fetching_instruction()
+            }),
+            "continue" | "c" => {
+                let stop = self.run(|_| false);
+                self.print_stop(stop, output)?;
+                Ok(())
+            }
+            "reverse-tick" | "rt" => {
+                self.command_reverse(&arguments, output, Simulator::step_back_tick)
+            }
+            "reverse-step" | "rs" => {
+                self.command_reverse(&arguments, output, Simulator::step_back_instruction)
+            }
+            "last-write" | "lw" => self.command_last_write(&arguments, output),
+            "registers" | "r" => self.print_registers(output).map_err(Into::into),
+            "disassemble" | "d" => self.command_disassemble(&arguments, output),
+            other => Err(DebuggerError::UnknownCommand {
+                command: other.to_owned(),
+            }
+            .into()),
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(CommandError::Io(error)) => Err(error),
+            Err(CommandError::Debugger(error)) => {
+                write!(output, "{error}")?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Ticks until `done` says enough after a tick, breakpoint
+    /// or watchpoint is hit, or CPU stops
+    pub fn run(&mut self, mut done: impl FnMut(&Simulator) -> bool) -> Stop {
+        loop {
+            if let Some(stop) = self.tick() {
+                return stop;
+            }
+
+            let cpu = self.simulator.cpu();
+            let breakpoint = self.points.iter().find_map(|(&id, point)| match point {
+                Point::Break(breakpoint) if breakpoint.is_hit(cpu) => Some(id),
+                _ => None,
+            });
+            if let Some(id) = breakpoint {
+                return Stop::Breakpoint { id };
+            }
+
+            if done(&self.simulator) {
+                return Stop::Stepped;
+            }
+        }
+    }
+
+    /// Executes single tick. Reports watchpoint it triggered
+    fn tick(&mut self) -> Option<Stop> {
+        let accesses = accesses(self.simulator.cpu());
+        let stall_ticks = self.simulator.counters().stall_ticks;
+
+        if !self.simulator.step_tick() {
+            return Some(Stop::Halted);
+        }
+
+        // microinstruction is held, access did not happen yet
+        if self.simulator.counters().stall_ticks != stall_ticks {
+            return None;
+        }
+
+        self.points.iter().find_map(|(&id, point)| {
+            let Point::Watch(watchpoint) = point else {
+                return None;
+            };
+            accesses
+                .iter()
+                .find(|&&(target, write)| {
+                    target == watchpoint.target && watchpoint.access.covers(write)
+                })
+                .map(|&(_, write)| Stop::Watchpoint { id, write })
+        })
+    }
+
+    fn command_break(
+        &mut self,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        let (location, condition) = match arguments.iter().position(|&word| word == "if") {
+            Some(index) => (&arguments[..index], Some(&arguments[index + 1..])),
+            None => (arguments, None),
+        };
+
+        let address = match location {
+            [] if condition.is_some() => None,
+            [location] => Some(self.parse_location(location)?),
+            _ => return Err(invalid_argument(&arguments.join(" ")).into()),
+        };
+        let condition = condition.map(parse_condition).transpose()?;
+
+        let breakpoint = Breakpoint { address, condition };
+        let id = self.add_breakpoint(breakpoint);
+        writeln!(
+            output,
+            "{id}: {}",
+            self.format_point(&Point::Break(breakpoint))
+        )?;
+
+        Ok(())
+    }
+
+    fn command_watch(
+        &mut self,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        let (target, access, default_access) = match arguments {
+            ["port", port, access @ ..] => {
+                let port = parse_number(port)
+                    .and_then(|port| RawPort::try_from(port).ok())
+                    .ok_or_else(|| invalid_argument(port))?;
+                (WatchTarget::Port(port), access, Access::Any)
+            }
+            [location, access @ ..] => (
+                WatchTarget::Memory(self.parse_location(location)?),
+                access,
+                Access::Write,
+            ),
+            [] => return Err(invalid_argument("").into()),
+        };
+        let access = match access {
+            [] => default_access,
+            [access] => Access::parse(access).ok_or_else(|| invalid_argument(access))?,
+            _ => return Err(invalid_argument(&access.join(" ")).into()),
+        };
+
+        let watchpoint = Watchpoint { target, access };
+        let id = self.add_watchpoint(watchpoint);
+        writeln!(
+            output,
+            "{id}: {}",
+            self.format_point(&Point::Watch(watchpoint))
+        )?;
+
+        Ok(())
+    }
+
+    fn command_delete(
+        &mut self,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        match arguments {
+            [] => {
+                self.points.clear();
+                writeln!(output, "Deleted all breakpoints and watchpoints")?;
+            }
+            [id] => {
+                let id = id.parse().map_err(|_| invalid_argument(id))?;
+                if !self.remove_point(id) {
+                    return Err(DebuggerError::UnknownPoint { id }.into());
+                }
+                writeln!(output, "Deleted {id}")?;
+            }
+            _ => return Err(invalid_argument(&arguments.join(" ")).into()),
+        }
+
+        Ok(())
+    }
+
+    fn command_info(&self, output: &mut impl Write) -> Result<(), CommandError> {
+        if self.points.is_empty() {
+            writeln!(output, "No breakpoints or watchpoints")?;
+        }
+        for (id, point) in &self.points {
+            writeln!(output, "{id}: {}", self.format_point(point))?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs until `step_done` was true after `n` ticks, n given as argument
+    fn command_run(
+        &mut self,
+        arguments: &[&str],
+        output: &mut impl Write,
+        mut step_done: impl FnMut(&Simulator) -> bool,
+    ) -> Result<(), CommandError> {
+        let count: usize = match arguments {
+            [] => 1,
+            [count] => count.parse().map_err(|_| invalid_argument(count))?,
+            _ => return Err(invalid_argument(&arguments.join(" ")).into()),
+        };
+
+        let mut steps = 0;
+        let stop = self.run(|simulator| {
+            if step_done(simulator) {
+                steps += 1;
+            }
+            steps >= count
+        });
+        self.print_stop(stop, output)?;
+
+        Ok(())
+    }
+
+    /// Steps back `n` times, n given as argument. Stops early
+    /// at the start of history
+    fn command_reverse(
+        &mut self,
+        arguments: &[&str],
+        output: &mut impl Write,
+        mut step_back: impl FnMut(&mut Simulator) -> bool,
+    ) -> Result<(), CommandError> {
+        let count: usize = match arguments {
+            [] => 1,
+            [count] => count.parse().map_err(|_| invalid_argument(count))?,
+            _ => return Err(invalid_argument(&arguments.join(" ")).into()),
+        };
+
+        for _ in 0..count {
+            if !step_back(&mut self.simulator) {
+                writeln!(output, "Reached start of history")?;
+                break;
+            }
+        }
+        self.print_stop(Stop::Stepped, output)?;
+
+        Ok(())
+    }
+
+    fn command_last_write(
+        &mut self,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        let [location] = arguments else {
+            return Err(invalid_argument(&arguments.join(" ")).into());
+        };
+        let address = self.parse_location(location)?;
+
+        if !self.simulator.run_back_to_write(address) {
+            writeln!(
+                output,
+                "No write of {} within history",
+                self.format_address(address)
+            )?;
+        }
+        self.print_stop(Stop::Stepped, output)?;
+
+        Ok(())
+    }
+
+    fn command_disassemble(
+        &self,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> Result<(), CommandError> {
+        let program_counter = self.simulator.registers().program_counter;
+        let (start, count) = match arguments {
+            [] => (
+                program_counter.saturating_sub(DISASSEMBLY_CONTEXT),
+                DISASSEMBLY_CONTEXT * 2 + 1,
+            ),
+            [location] => (self.parse_location(location)?, DISASSEMBLY_CONTEXT * 2 + 1),
+            [location, count] => (
+                self.parse_location(location)?,
+                count.parse().map_err(|_| invalid_argument(count))?,
+            ),
+            _ => return Err(invalid_argument(&arguments.join(" ")).into()),
+        };
+
+        for address in (start..=RawAddress::MAX).take(count as usize) {
+            if let Some(label) = self.debug_info.label_at(address) {
+                writeln!(output, "{label}:")?;
+            }
+            let marker = if address == program_counter {
+                "=>"
+            } else {
+                "  "
+            };
+            writeln!(
+                output,
+                "{marker} {address:#06x}  {}",
+                self.disassemble(address)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn print_stop(&self, stop: Stop, output: &mut impl Write) -> io::Result<()> {
+        match stop {
+            Stop::Stepped => (),
+            Stop::Breakpoint { id } => writeln!(output, "Breakpoint {id}")?,
+            Stop::Watchpoint { id, write } => {
+                let Some(Point::Watch(watchpoint)) = self.points.get(&id) else {
+                    unreachable!("watchpoint which stopped execution exists");
+                };
+                let access = if write { "write" } else { "read" };
+                match watchpoint.target {
+                    WatchTarget::Memory(address) => writeln!(
+                        output,
+                        "Watchpoint {id}: {access} of {}, value {}",
+                        self.format_address(address),
+                        self.simulator.memory()[address]
+                    )?,
+                    WatchTarget::Port(port) => {
+                        writeln!(output, "Watchpoint {id}: {access} of port {port}")?
+                    }
+                }
+            }
+            Stop::Halted => {
+                let reason = self
+                    .simulator
+                    .halt_reason()
+                    .expect("halted CPU has halt reason");
+                return match reason {
+                    HaltReason::Fault(fault) => write!(output, "{fault}"),
+                    other => writeln!(output, "Stopped: {other}"),
+                };
+            }
+        }
+
+        let cpu = self.simulator.cpu();
+        let counters = cpu.counters();
+        let address = self.current_instruction();
+        let position = if cpu.is_fetching() { "at" } else { "in" };
+        writeln!(
+            output,
+            "tick {}, instruction {}, {position} {}: {}",
+            counters.ticks,
+            counters.instructions,
+            self.format_address(address),
+            self.disassemble(address)
+        )
+    }
+
+    /// Address of instruction being executed, or of the next one
+    /// between instructions. PC already points past the former
+    pub fn current_instruction(&self) -> RawAddress {
+        let cpu = self.simulator.cpu();
+        match cpu.last_instruction() {
+            Some(instruction) if !cpu.is_fetching() => instruction.address,
+            _ => cpu.registers().program_counter,
+        }
+    }
+
+    fn print_registers(&self, output: &mut impl Write) -> io::Result<()> {
+        let cpu = self.simulator.cpu();
+        let counters = cpu.counters();
+
+        write!(output, "{cpu}")?;
+        writeln!(
+            output,
+            "Signals: {}",
+            format_microinstruction(&cpu.current_microinstruction())
+        )?;
+        writeln!(
+            output,
+            "Ticks: {}, instructions: {}, memory ready: {}",
+            counters.ticks,
+            counters.instructions,
+            cpu.memory_ready()
+        )
+    }
+
+    fn format_point(&self, point: &Point) -> String {
+        match point {
+            Point::Break(Breakpoint { address, condition }) => {
+                let mut text = String::from("break");
+                if let Some(address) = address {
+                    text += &format!(" {}", self.format_address(*address));
+                }
+                if let Some(condition) = condition {
+                    text += &format!(" if {condition}");
+                }
+                text
+            }
+            Point::Watch(Watchpoint { target, access }) => {
+                let target = match target {
+                    WatchTarget::Memory(address) => self.format_address(*address),
+                    WatchTarget::Port(port) => format!("port {port}"),
+                };
+                let access = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                    Access::Any => "access",
+                };
+                format!("watch {target} {access}")
+            }
+        }
+    }
+
+    /// Address along with closest label, e.g. `0x0026 <start+2>`
+    fn format_address(&self, address: RawAddress) -> String {
+        match self.debug_info.nearest_label(address) {
+            Some((label, 0)) => format!("{address:#06x} <{label}>"),
+            Some((label, offset)) => format!("{address:#06x} <{label}+{offset}>"),
+            None => format!("{address:#06x}"),
+        }
+    }
+
+    /// Cell content in assembler syntax
+    pub fn disassemble(&self, address: RawAddress) -> String {
+        match self.simulator.memory()[address] {
+            MemoryItem::Data(value) => format!("word {value:#x}"),
+            MemoryItem::Command(command) => disassemble_command(command, address, &self.debug_info),
+        }
+    }
+
+    /// Address, label or `label+offset`
+    pub fn parse_location(&self, location: &str) -> Result<RawAddress, DebuggerError> {
+        if let Some(address) = parse_number(location) {
+            return RawAddress::try_from(address).map_err(|_| invalid_argument(location));
+        }
+
+        let (label, offset) = match location.split_once('+') {
+            Some((label, offset)) => (
+                label,
+                parse_number(offset)
+                    .and_then(|offset| RawAddress::try_from(offset).ok())
+                    .ok_or_else(|| invalid_argument(location))?,
+            ),
+            None => (location, 0),
+        };
+
+        self.debug_info
+            .address_of(label)
+            .map(|address| address.wrapping_add(offset))
+            .ok_or_else(|| DebuggerError::UnknownLabel {
+                label: label.to_owned(),
+            })
+    }
+}
+
+enum CommandError {
+    Io(io::Error),
+    Debugger(DebuggerError),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(error: io::Error) -> Self {
+        CommandError::Io(error)
+    }
+}
+
+impl From<DebuggerError> for CommandError {
+    fn from(error: DebuggerError) -> Self {
+        CommandError::Debugger(error)
+    }
+}
+
+fn invalid_argument(argument: &str) -> DebuggerError {
+    DebuggerError::InvalidArgument {
+        argument: argument.to_owned(),
+    }
+}
+
+/// Words of `<register> <operator> <value>`, spaces around operator are optional
+fn parse_condition(words: &[&str]) -> Result<Condition, DebuggerError> {
+    let text = words.concat();
+    let invalid = || invalid_argument(&words.join(" "));
+
+    let (register, comparison, value) = Comparison::OPERATORS
+        .iter()
+        .find_map(|&(operator, comparison)| {
+            let (register, value) = text.split_once(operator)?;
+            Some((register, comparison, value))
+        })
+        .ok_or_else(invalid)?;
+
+    Ok(Condition {
+        register: Register::parse(register).ok_or_else(invalid)?,
+        comparison,
+        value: parse_number(value).ok_or_else(invalid)?,
+    })
+}
+
+/// Decimal or `0x` hex
+fn parse_number(number: &str) -> Option<u32> {
+    match number.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => number.parse().ok(),
+    }
+}
+
+/// Assembler syntax, operands resolved to labels where possible
+pub fn disassemble_command(
+    command: CompiledCommand,
+    address: RawAddress,
+    debug_info: &DebugInfo,
+) -> String {
+    let opcode = command.opcode.to_string().to_lowercase();
+    let operand = command.operand.operand;
+    // relative operand counts from the next command
+    let target = || address.wrapping_add(1).wrapping_add(operand);
+    let symbol = |address: RawAddress| {
+        debug_info
+            .label_at(address)
+            .map_or_else(|| format!("{address:#06x}"), str::to_owned)
+    };
+
+    match command.operand.operand_type {
+        OperandType::None => opcode,
+        OperandType::Immediate => format!("{opcode} {operand}"),
+        OperandType::Absolute => format!("{opcode} !{}", symbol(operand)),
+        OperandType::Relative => format!("{opcode} {}", symbol(target())),
+        OperandType::Indirect => format!("{opcode} ({})", symbol(target())),
+    }
+}
+
+/// Memory cells and ports which current microinstruction accesses.
+/// `true` marks writes
+fn accesses(cpu: &Cpu) -> Vec<(WatchTarget, bool)> {
+    let micro_instruction = cpu.current_microinstruction();
+    let registers = cpu.registers();
+    let mut accesses = Vec::new();
+
+    if micro_instruction.contains(&Signal::SELECT_MEM)
+        && micro_instruction.contains(&Signal::WRITE_DATA)
+    {
+        accesses.push((WatchTarget::Memory(registers.address), false));
+    }
+    if micro_instruction.contains(&Signal::WRITE_MEM) {
+        accesses.push((WatchTarget::Memory(registers.address), true));
+    }
+
+    // data register holds port during IO
+    let port = registers.data.data().map(|port| port as RawPort);
+    if let Some(port) = port.filter(|_| micro_instruction.contains(&Signal::IO)) {
+        if micro_instruction.contains(&Signal::WRITE_IO) {
+            accesses.push((WatchTarget::Port(port), true));
+        }
+        if micro_instruction.contains(&Signal::WRITE_ACCUMULATOR) {
+            accesses.push((WatchTarget::Port(port), false));
+        }
+    }
+
+    accesses
+}
@@ -0,0 +1,130 @@
+//! Runs two CPUs lock-step and reports first tick where they diverge.
+//! Intended to check hardwired control unit against microcode
+
+use std::{error::Error, fmt::Display};
+
+use crate::cpu::{
+    format_microinstruction, Cpu, CpuFault, HaltReason, Microinstruction, ERROR_EXIT_CODE,
+};
+
+/// Process exit status when control units diverge, past ones of halt reasons
+pub const DIVERGENCE_EXIT_CODE: i32 = ERROR_EXIT_CODE + 5;
+
+#[derive(Debug)]
+pub enum DivergenceKind {
+    Signals {
+        left: Microinstruction,
+        right: Microinstruction,
+    },
+    ArchitecturalState,
+    /// one CPU stopped while other did not, or they stopped differently
+    HaltReason {
+        left: Option<HaltReason>,
+        right: Option<HaltReason>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub tick: usize,
+    pub kind: DivergenceKind,
+    pub left: String,
+    pub right: String,
+}
+
+impl Error for Divergence {}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            DivergenceKind::Signals { left, right } => {
+                writeln!(f, "Signals diverged at tick {}", self.tick)?;
+                writeln!(f, "  left:  {}", format_microinstruction(left))?;
+                writeln!(f, "  right: {}", format_microinstruction(right))?;
+            }
+            DivergenceKind::ArchitecturalState => {
+                writeln!(f, "Architectural state diverged after tick {}", self.tick)?;
+            }
+            DivergenceKind::HaltReason { left, right } => {
+                let reason = |reason: &Option<HaltReason>| match reason {
+                    Some(reason) => reason.to_string(),
+                    None => "running".to_owned(),
+                };
+                writeln!(f, "Halt reasons diverged at tick {}", self.tick)?;
+                writeln!(f, "  left:  {}", reason(left))?;
+                writeln!(f, "  right: {}", reason(right))?;
+            }
+        }
+
+        writeln!(f, "Left:")?;
+        writeln!(f, "{}", self.left)?;
+        writeln!(f, "Right:")?;
+        write!(f, "{}", self.right)
+    }
+}
+
+/// Returns amount of ticks executed and halt reason when both CPUs
+/// stopped in agreement. Stopping by fault or limit agrees too
+pub fn run_lock_step(
+    mut left: Cpu,
+    mut right: Cpu,
+) -> Result<(usize, HaltReason), Box<Divergence>> {
+    let mut tick = 0;
+
+    loop {
+        let left_signals = left.current_microinstruction();
+        let right_signals = right.current_microinstruction();
+        if left_signals != right_signals {
+            return Err(Box::new(Divergence {
+                tick,
+                kind: DivergenceKind::Signals {
+                    left: left_signals,
+                    right: right_signals,
+                },
+                left: left.to_string(),
+                right: right.to_string(),
+            }));
+        }
+
+        let left_running = left.tick();
+        let right_running = right.tick();
+        let left_reason = comparable(left.halt_reason());
+        let right_reason = comparable(right.halt_reason());
+        if left_running != right_running || left_reason != right_reason {
+            return Err(Box::new(Divergence {
+                tick,
+                kind: DivergenceKind::HaltReason {
+                    left: left.halt_reason().cloned(),
+                    right: right.halt_reason().cloned(),
+                },
+                left: left.to_string(),
+                right: right.to_string(),
+            }));
+        }
+        if let (false, Some(reason)) = (left_running, left_reason) {
+            return Ok((tick, reason));
+        }
+
+        if !left.architectural_state_eq(&right) {
+            return Err(Box::new(Divergence {
+                tick,
+                kind: DivergenceKind::ArchitecturalState,
+                left: left.to_string(),
+                right: right.to_string(),
+            }));
+        }
+
+        tick += 1;
+    }
+}
+
+/// Fault of hardwired control unit has no control store address
+fn comparable(reason: Option<&HaltReason>) -> Option<HaltReason> {
+    reason.cloned().map(|reason| match reason {
+        HaltReason::Fault(fault) => HaltReason::Fault(CpuFault {
+            micro_instruction: None,
+            ..fault
+        }),
+        reason => reason,
+    })
+}
@@ -0,0 +1,491 @@
+//! GDB remote serial protocol stub. Serves single debugger connected over
+//! TCP on localhost. Execution goes through `Debugger`, so breakpoints and
+//! watchpoints behave the same as in `cpu debug`.
+//!
+//! GDB addresses bytes, so every memory cell is shown as 4 little-endian
+//! bytes, and `pc` and `ar` count bytes: cell `n` lies at `4 * n`.
+//! Instructions have no binary form, they are shown as
+//! `operand | operand type << 16 | opcode << 24`. Writing the same value
+//! back leaves instruction in place, any other value turns cell into data.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use isa::{CompiledCommand, MemoryItem, RawAddress, MEMORY_DATA_CELL_SIZE, MEMORY_SIZE};
+
+use crate::{
+    cpu::{HaltReason, Status},
+    debugger::{Access, Breakpoint, Debugger, Stop, WatchTarget, Watchpoint},
+    Simulator,
+};
+
+const CELL_SIZE: u32 = MEMORY_DATA_CELL_SIZE as u32;
+/// ticks between checks whether GDB asks to interrupt execution
+const INTERRUPT_CHECK_PERIOD: usize = 1024;
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+/// reported for every stop but HALT: fault, exhausted input, limits
+const SIGSEGV: u8 = 11;
+
+/// acc, pc, ar, dr, cr, status
+const REGISTERS: usize = 6;
+const TARGET_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.csa-lab3.cpu">
+    <flags id="status_flags" size="4">
+      <field name="Z" start="0" end="0"/>
+      <field name="C" start="1" end="1"/>
+    </flags>
+    <reg name="acc" bitsize="32" type="uint32" regnum="0"/>
+    <reg name="pc" bitsize="32" type="code_ptr" regnum="1"/>
+    <reg name="ar" bitsize="32" type="data_ptr" regnum="2"/>
+    <reg name="dr" bitsize="32" type="uint32" regnum="3"/>
+    <reg name="cr" bitsize="32" type="uint32" regnum="4"/>
+    <reg name="status" bitsize="32" type="status_flags" regnum="5"/>
+  </feature>
+</target>
+"#;
+
+pub struct GdbStub {
+    debugger: Debugger,
+    // debugger points inserted by Z packets: (type, address, length) -> ids
+    points: HashMap<(u8, u32, u32), Vec<usize>>,
+}
+
+impl GdbStub {
+    pub fn new(simulator: Simulator) -> Self {
+        Self {
+            debugger: Debugger::new(simulator, Default::default()),
+            points: HashMap::new(),
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        self.debugger.simulator()
+    }
+
+    /// Waits for GDB on localhost and serves it until it detaches
+    pub fn listen(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+
+        self.serve(stream)
+    }
+
+    /// Serves already connected GDB until it detaches or disconnects
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut connection = Connection::new(stream)?;
+
+        while let Some(packet) = connection.receive()? {
+            match packet.as_str() {
+                "D" => return connection.send("OK"),
+                "k" => return Ok(()),
+                "QStartNoAckMode" => {
+                    connection.send("OK")?;
+                    connection.acknowledge = false;
+                }
+                packet => {
+                    let reply = self.reply(packet, &mut connection);
+                    connection.send(&reply)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unsupported packets get empty reply, malformed ones get error
+    fn reply(&mut self, packet: &str, connection: &mut Connection) -> String {
+        let Some((command, arguments)) = packet.split_at_checked(1) else {
+            return String::new();
+        };
+
+        let reply = match command {
+            "?" => Some(self.stop_reply(Stop::Stepped)),
+            "q" => self.query(arguments),
+            "H" | "T" => Some("OK".into()),
+            "g" => Some(self.read_registers()),
+            "G" => self.write_registers(arguments),
+            "p" => self.read_register(arguments),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "c" => self.resume(arguments, false, connection),
+            "s" => self.resume(arguments, true, connection),
+            "Z" => self.insert_point(arguments),
+            "z" => self.remove_point(arguments),
+            _ => Some(String::new()),
+        };
+
+        reply.unwrap_or_else(|| "E01".into())
+    }
+
+    fn query(&self, query: &str) -> Option<String> {
+        if query.starts_with("Supported") {
+            return Some("PacketSize=1000;qXfer:features:read+;swbreak+;QStartNoAckMode+".into());
+        }
+
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, length) = range.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            let length = usize::from_str_radix(length, 16).ok()?;
+            let chunk = TARGET_DESCRIPTION.get(offset..)?;
+            return Some(match chunk.get(..length) {
+                Some(chunk) if chunk.len() < TARGET_DESCRIPTION.len() - offset => {
+                    format!("m{chunk}")
+                }
+                _ => format!("l{chunk}"),
+            });
+        }
+
+        Some(
+            match query {
+                "Attached" => "1",
+                "C" => "QC1",
+                "fThreadInfo" => "m1",
+                "sThreadInfo" => "l",
+                _ => "",
+            }
+            .into(),
+        )
+    }
+
+    fn register(&self, number: usize) -> Option<u32> {
+        let simulator = self.debugger.simulator();
+        let registers = simulator.registers();
+
+        Some(match number {
+            0 => registers.accumulator,
+            1 => registers.program_counter as u32 * CELL_SIZE,
+            2 => registers.address as u32 * CELL_SIZE,
+            3 => registers.data.encode(),
+            4 => registers.command.encode(),
+            5 => {
+                let Status { zero, carry } = simulator.status();
+                zero as u32 | (carry as u32) << 1
+            }
+            _ => return None,
+        })
+    }
+
+    /// Returns `None` if register does not exist or can not hold value
+    fn set_register(&mut self, number: usize, value: u32) -> Option<()> {
+        let to_address = |value: u32| RawAddress::try_from(value / CELL_SIZE).ok();
+        let simulator = self.debugger.simulator_mut();
+
+        match number {
+            0 => simulator.registers_mut().accumulator = value,
+            1 => simulator.registers_mut().program_counter = to_address(value)?,
+            2 => simulator.registers_mut().address = to_address(value)?,
+            3 => {
+                let registers = simulator.registers_mut();
+                registers.data = write_cell(registers.data, value);
+            }
+            4 => simulator.registers_mut().command = CompiledCommand::decode(value)?,
+            5 => simulator.set_status(Status {
+                zero: value & 1 != 0,
+                carry: value & 2 != 0,
+            }),
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTERS)
+            .filter_map(|number| self.register(number))
+            .map(|value| hex(&value.to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, values: &str) -> Option<String> {
+        let bytes = parse_hex(values)?;
+        for (number, value) in bytes.chunks_exact(4).take(REGISTERS).enumerate() {
+            let value = u32::from_le_bytes(value.try_into().expect("chunk of 4 bytes"));
+            self.set_register(number, value)?;
+        }
+
+        Some("OK".into())
+    }
+
+    fn read_register(&self, number: &str) -> Option<String> {
+        let number = usize::from_str_radix(number, 16).ok()?;
+
+        Some(hex(&self.register(number)?.to_le_bytes()))
+    }
+
+    fn write_register(&mut self, arguments: &str) -> Option<String> {
+        let (number, value) = arguments.split_once('=')?;
+        let number = usize::from_str_radix(number, 16).ok()?;
+        let value = u32::from_le_bytes(parse_hex(value)?.try_into().ok()?);
+        self.set_register(number, value)?;
+
+        Some("OK".into())
+    }
+
+    fn read_memory(&self, arguments: &str) -> Option<String> {
+        let (address, length) = parse_range(arguments)?;
+        let memory = self.debugger.simulator().memory();
+
+        let bytes: Vec<u8> = (address..address + length)
+            .map(|byte| {
+                let cell = memory[(byte / CELL_SIZE) as usize].encode();
+                cell.to_le_bytes()[(byte % CELL_SIZE) as usize]
+            })
+            .collect();
+
+        Some(hex(&bytes))
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> Option<String> {
+        let (range, data) = arguments.split_once(':')?;
+        let (address, length) = parse_range(range)?;
+        let data = parse_hex(data)?;
+        if data.len() != length as usize {
+            return None;
+        }
+
+        let memory = self.debugger.simulator_mut().memory_mut();
+        for (byte, value) in (address..).zip(data) {
+            let cell = &mut memory[(byte / CELL_SIZE) as usize];
+            let mut bytes = cell.encode().to_le_bytes();
+            bytes[(byte % CELL_SIZE) as usize] = value;
+            *cell = write_cell(*cell, u32::from_le_bytes(bytes));
+        }
+
+        Some("OK".into())
+    }
+
+    /// Continues or steps single instruction, optionally from new address
+    fn resume(&mut self, address: &str, step: bool, connection: &mut Connection) -> Option<String> {
+        if !address.is_empty() {
+            self.set_register(1, u32::from_str_radix(address, 16).ok()?)?;
+        }
+
+        let mut interrupted = false;
+        let stop = self.debugger.run(|simulator| {
+            if step && simulator.cpu().control_unit().is_fetching_instruction() {
+                return true;
+            }
+
+            let check = simulator.counters().ticks % INTERRUPT_CHECK_PERIOD == 0;
+            interrupted = check && connection.take_interrupt();
+            interrupted
+        });
+
+        if interrupted {
+            return Some(format!("S{SIGINT:02x}"));
+        }
+
+        Some(self.stop_reply(stop))
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Stepped if self.debugger.simulator().is_halted() => self.stop_reply(Stop::Halted),
+            Stop::Stepped => format!("S{SIGTRAP:02x}"),
+            Stop::Breakpoint { .. } => format!("T{SIGTRAP:02x}swbreak:;"),
+            Stop::Watchpoint { id, .. } => {
+                let watched = self
+                    .points
+                    .iter()
+                    .find(|(_, ids)| ids.contains(&id))
+                    .map(|(&(kind, address, _), _)| (kind, address));
+
+                match watched {
+                    Some((kind, address)) => {
+                        let name = match kind {
+                            2 => "watch",
+                            3 => "rwatch",
+                            _ => "awatch",
+                        };
+                        format!("T{SIGTRAP:02x}{name}:{address:x};")
+                    }
+                    None => format!("S{SIGTRAP:02x}"),
+                }
+            }
+            Stop::Halted => match self.debugger.simulator().halt_reason() {
+                Some(HaltReason::Halt { exit_code }) => format!("W{:02x}", *exit_code as u8),
+                _ => format!("S{SIGSEGV:02x}"),
+            },
+        }
+    }
+
+    /// `type,address,kind`. Software and hardware breakpoints are the same,
+    /// watchpoints cover every cell touched by `address..address + length`
+    fn insert_point(&mut self, arguments: &str) -> Option<String> {
+        let (kind, address, length) = parse_point(arguments)?;
+        let cell = |byte: u32| RawAddress::try_from(byte / CELL_SIZE).ok();
+
+        let ids = match kind {
+            0 | 1 => vec![self.debugger.add_breakpoint(Breakpoint {
+                address: Some(cell(address)?),
+                condition: None,
+            })],
+            2..=4 => {
+                let access = match kind {
+                    2 => Access::Write,
+                    3 => Access::Read,
+                    _ => Access::Any,
+                };
+                let first = cell(address)?;
+                let last = cell(address + length.max(1) - 1)?;
+                (first..=last)
+                    .map(|cell| {
+                        self.debugger.add_watchpoint(Watchpoint {
+                            target: WatchTarget::Memory(cell),
+                            access,
+                        })
+                    })
+                    .collect()
+            }
+            _ => return Some(String::new()),
+        };
+        self.points
+            .entry((kind, address, length))
+            .or_default()
+            .extend(ids);
+
+        Some("OK".into())
+    }
+
+    fn remove_point(&mut self, arguments: &str) -> Option<String> {
+        let point = parse_point(arguments)?;
+        for id in self.points.remove(&point).unwrap_or_default() {
+            self.debugger.remove_point(id);
+        }
+
+        Some("OK".into())
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // cleared by QStartNoAckMode
+    acknowledge: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            acknowledge: true,
+        })
+    }
+
+    /// Payload of next valid packet. `None` when GDB disconnects.
+    /// Acknowledgements and interrupts outside of execution are skipped
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut start = Vec::new();
+            if self.reader.read_until(b'$', &mut start)? == 0 || start.last() != Some(&b'$') {
+                return Ok(None);
+            }
+
+            let mut payload = Vec::new();
+            self.reader.read_until(b'#', &mut payload)?;
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            if payload.pop() != Some(b'#') {
+                return Ok(None);
+            }
+
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                .is_some_and(|checksum| checksum == sum(&payload));
+            if self.acknowledge {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        write!(self.writer, "${payload}#{:02x}", sum(payload.as_bytes()))?;
+        self.writer.flush()
+    }
+
+    /// Consumes interrupt request if GDB sent one
+    fn take_interrupt(&mut self) -> bool {
+        if let Some(&byte) = self.reader.buffer().first() {
+            if byte == INTERRUPT {
+                self.reader.consume(1);
+            }
+            return byte == INTERRUPT;
+        }
+
+        let stream = self.reader.get_ref();
+        let mut byte = [0];
+        let peeked = stream
+            .set_nonblocking(true)
+            .and_then(|_| stream.peek(&mut byte));
+        if stream.set_nonblocking(false).is_err() {
+            return false;
+        }
+
+        let interrupt = matches!(peeked, Ok(1)) && byte[0] == INTERRUPT;
+        interrupt && self.reader.read_exact(&mut byte).is_ok()
+    }
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// `address,length` in hex, which has to lie within memory
+fn parse_range(range: &str) -> Option<(u32, u32)> {
+    let (address, length) = range.split_once(',')?;
+    let address = u32::from_str_radix(address, 16).ok()?;
+    let length = u32::from_str_radix(length, 16).ok()?;
+
+    let end = address.checked_add(length)?;
+    (end as usize <= MEMORY_SIZE * CELL_SIZE as usize).then_some((address, length))
+}
+
+/// `type,address,kind` of Z and z packets
+fn parse_point(arguments: &str) -> Option<(u8, u32, u32)> {
+    let mut fields = arguments.splitn(3, ',');
+    let kind = fields.next()?.parse().ok()?;
+    let address = u32::from_str_radix(fields.next()?, 16).ok()?;
+    // conditions and commands after `;` are not supported
+    let length = fields.next()?.split(';').next()?;
+    let length = u32::from_str_radix(length, 16).ok()?;
+
+    Some((kind, address, length))
+}
+
+/// Keeps instruction if value is its own encoding
+fn write_cell(cell: MemoryItem, value: u32) -> MemoryItem {
+    if cell.encode() == value {
+        cell
+    } else {
+        MemoryItem::Data(value)
+    }
+}
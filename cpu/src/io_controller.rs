@@ -10,19 +10,70 @@
 // receive buffer
 // device has logic to determine when to start outputting data
 
-use std::{collections::HashMap, fmt::Debug, io::Write, mem::size_of};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::{Debug, Display},
+    io::Write,
+    mem::size_of,
+};
 
 use isa::RawPort;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct IOController {
     devices: HashMap<RawPort, Box<dyn Device>>,
+    // everything written to devices
+    written: Vec<u8>,
+    // writes are recorded, but not delivered to devices
+    replay: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IOControllerState {
+    pub devices: BTreeMap<RawPort, Value>,
+    pub written: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum DeviceStateError {
+    MissingDevice {
+        port: RawPort,
+    },
+    InvalidState {
+        port: RawPort,
+        error: serde_json::Error,
+    },
+}
+
+impl Error for DeviceStateError {}
+
+impl Display for DeviceStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceStateError::MissingDevice { port } => {
+                writeln!(f, "No device is connected to port {port}")
+            }
+            DeviceStateError::InvalidState { port, error } => {
+                writeln!(f, "Invalid state of device at port {port}: {error}")
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct SimpleInputOutput {
     output: Vec<u8>,
     cursor: usize,
+    echo: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SimpleInputOutputState {
+    output: Vec<u8>,
+    cursor: usize,
 }
 
 impl SimpleInputOutput {
@@ -37,32 +88,83 @@ impl SimpleInputOutput {
         output.push(length);
         output.extend_from_slice(string.as_bytes());
 
-        Self { output, cursor: 0 }
+        Self {
+            output,
+            cursor: 0,
+            echo: true,
+        }
+    }
+
+    /// Device discards written data instead of printing it to stdout
+    pub fn silent(mut self) -> Self {
+        self.echo = false;
+
+        self
     }
 }
 
 impl Device for SimpleInputOutput {
-    fn read_from_device(&mut self) -> u8 {
-        let data = self.output[self.cursor];
+    fn read_from_device(&mut self) -> Option<u8> {
+        let data = self.output.get(self.cursor).copied()?;
         self.cursor += 1;
 
-        data
+        Some(data)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.cursor >= self.output.len()
+    }
+
+    fn state(&self) -> Value {
+        serde_json::to_value(SimpleInputOutputState {
+            output: self.output.clone(),
+            cursor: self.cursor,
+        })
+        .unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: Value) -> serde_json::Result<()> {
+        let SimpleInputOutputState { output, cursor } = serde_json::from_value(state)?;
+        self.output = output;
+        self.cursor = cursor;
+
+        Ok(())
     }
 
     fn write_to_device(&mut self, payload: u8) {
-        std::io::stdout().write_all(&[payload]).unwrap();
+        if self.echo {
+            std::io::stdout().write_all(&[payload]).unwrap();
+        }
     }
 }
 
 pub trait Device: Debug {
-    fn read_from_device(&mut self) -> u8;
+    /// `None` when device has no data left
+    fn read_from_device(&mut self) -> Option<u8>;
     fn write_to_device(&mut self, payload: u8);
+
+    /// Device has no data left to read
+    fn is_exhausted(&self) -> bool {
+        false
+    }
+
+    /// Internal state for snapshots. Stateless devices have none
+    fn state(&self) -> Value {
+        Value::Null
+    }
+
+    fn restore(&mut self, state: Value) -> serde_json::Result<()> {
+        let _ = state;
+        Ok(())
+    }
 }
 
 impl IOController {
     pub fn new() -> Self {
         IOController {
             devices: HashMap::new(),
+            written: Vec::new(),
+            replay: false,
         }
     }
 
@@ -72,14 +174,89 @@ impl IOController {
         self
     }
 
-    pub fn read(&mut self, device_address: RawPort) -> u8 {
+    /// Replaces device at port, e.g. to provide fresh input between runs.
+    /// Returns previously connected device
+    pub fn reconnect(
+        &mut self,
+        address: RawPort,
+        device: Box<dyn Device>,
+    ) -> Option<Box<dyn Device>> {
+        self.devices.insert(address, device)
+    }
+
+    pub fn device(&self, device_address: RawPort) -> Option<&dyn Device> {
+        self.devices
+            .get(&device_address)
+            .map(|device| device.as_ref())
+    }
+
+    pub fn device_mut(&mut self, device_address: RawPort) -> Option<&mut (dyn Device + 'static)> {
         self.devices
             .get_mut(&device_address)
-            .map_or(0, |device| device.read_from_device())
+            .map(|device| device.as_mut())
+    }
+
+    /// Missing device reads as zero
+    pub fn read(&mut self, device_address: RawPort) -> Option<u8> {
+        self.devices
+            .get_mut(&device_address)
+            .map_or(Some(0), |device| device.read_from_device())
+    }
+
+    /// Missing device is never exhausted: it reads as zero
+    pub fn is_exhausted(&self, device_address: RawPort) -> bool {
+        self.devices
+            .get(&device_address)
+            .is_some_and(|device| device.is_exhausted())
+    }
+
+    pub fn state(&self) -> IOControllerState {
+        IOControllerState {
+            devices: self
+                .devices
+                .iter()
+                .map(|(&port, device)| (port, device.state()))
+                .collect(),
+            written: self.written.clone(),
+        }
+    }
+
+    /// Devices themselves are not part of state, they have to be connected beforehand
+    pub fn restore(&mut self, state: IOControllerState) -> Result<(), DeviceStateError> {
+        for (port, device_state) in state.devices {
+            self.devices
+                .get_mut(&port)
+                .ok_or(DeviceStateError::MissingDevice { port })?
+                .restore(device_state)
+                .map_err(|error| DeviceStateError::InvalidState { port, error })?;
+        }
+        self.written = state.written;
+
+        Ok(())
+    }
+
+    pub fn clear_written(&mut self) {
+        self.written.clear();
+    }
+
+    /// Bytes written to devices so far
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Replayed execution already reached devices once
+    pub fn set_replay(&mut self, replay: bool) {
+        self.replay = replay;
     }
 
     pub fn write(&mut self, device_address: RawPort, payload: u8) {
-        if let Some(device) = self.devices
-            .get_mut(&device_address) { device.write_to_device(payload) }
+        self.written.push(payload);
+        if self.replay {
+            return;
+        }
+
+        if let Some(device) = self.devices.get_mut(&device_address) {
+            device.write_to_device(payload)
+        }
     }
 }
@@ -0,0 +1,125 @@
+//! Wait states of memory. Memory signals ready only after latency of
+//! accessed address has passed, control unit holds current microinstruction
+//! until then. Address ranges may be slower or faster than the rest.
+
+use std::{error::Error, fmt::Display};
+
+use isa::RawAddress;
+use serde::{Deserialize, Serialize};
+
+/// Ticks access takes in addition to the tick which issued it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Latency {
+    pub read: usize,
+    pub write: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyRegion {
+    pub start: RawAddress,
+    /// inclusive
+    pub end: RawAddress,
+    pub latency: Latency,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryLatency {
+    /// latency of addresses outside of every region
+    pub default: Latency,
+    /// later region wins where regions overlap
+    pub regions: Vec<LatencyRegion>,
+}
+
+#[derive(Debug)]
+pub enum LatencyError {
+    InvalidSpec { spec: String },
+    EmptyRegion { start: RawAddress, end: RawAddress },
+}
+
+impl Error for LatencyError {}
+
+impl Display for LatencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LatencyError::InvalidSpec { spec } => {
+                writeln!(f, "Invalid memory latency `{spec}`")
+            }
+            LatencyError::EmptyRegion { start, end } => {
+                writeln!(f, "Region {start:#06x}-{end:#06x} ends before it starts")
+            }
+        }
+    }
+}
+
+impl Latency {
+    /// `read=R,write=W`, `ticks=N` sets both. Missing keys keep values of `base`
+    pub fn parse(spec: &str, base: Latency) -> Result<Self, LatencyError> {
+        let invalid = || LatencyError::InvalidSpec {
+            spec: spec.to_owned(),
+        };
+        let mut latency = base;
+
+        for pair in spec.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+            let ticks = value.parse().map_err(|_| invalid())?;
+            match key {
+                "read" => latency.read = ticks,
+                "write" => latency.write = ticks,
+                "ticks" => {
+                    latency = Latency {
+                        read: ticks,
+                        write: ticks,
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(latency)
+    }
+}
+
+impl LatencyRegion {
+    /// `START-END:read=R,write=W`. Addresses are decimal or `0x` hex.
+    /// Missing keys keep values of `base`
+    pub fn parse(spec: &str, base: Latency) -> Result<Self, LatencyError> {
+        let invalid = || LatencyError::InvalidSpec {
+            spec: spec.to_owned(),
+        };
+        let (range, latency) = spec.split_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let start = parse_address(start).ok_or_else(invalid)?;
+        let end = parse_address(end).ok_or_else(invalid)?;
+        if end < start {
+            return Err(LatencyError::EmptyRegion { start, end });
+        }
+
+        Ok(Self {
+            start,
+            end,
+            latency: Latency::parse(latency, base)?,
+        })
+    }
+
+    pub fn contains(&self, address: RawAddress) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+impl MemoryLatency {
+    pub fn of(&self, address: RawAddress) -> Latency {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.contains(address))
+            .map_or(self.default, |region| region.latency)
+    }
+}
+
+fn parse_address(address: &str) -> Option<RawAddress> {
+    let address = address.trim();
+    match address.strip_prefix("0x") {
+        Some(hex) => RawAddress::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => address.parse().ok(),
+    }
+}
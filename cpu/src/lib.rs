@@ -0,0 +1,21 @@
+pub mod cache;
+pub mod code_coverage;
+pub mod cpu;
+pub mod dap;
+pub mod debugger;
+pub mod differential;
+pub mod gdb;
+pub mod io_controller;
+pub mod latency;
+pub mod memory;
+pub mod multicore;
+pub mod observer;
+pub mod profiler;
+pub mod protection;
+pub mod shadow_memory;
+pub mod simulator;
+pub mod trace;
+pub mod trace_diff;
+pub mod vcd;
+
+pub use self::simulator::Simulator;
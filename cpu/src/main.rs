@@ -2,28 +2,55 @@ use std::{
     env,
     error::Error,
     fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter},
+    net::TcpListener,
     path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    time::Duration,
 };
 
-use cli_utils::{check_empty_arguments, ConfigurationError};
-use io_controller::{IOController, SimpleInputOutput};
-use isa::CompiledProgram;
-use memory::Memory;
-
-use crate::cpu::Cpu;
-
-mod cpu;
-mod io_controller;
-mod memory;
+use cli_utils::{check_empty_arguments, take_flag, take_option, ConfigurationError};
+use cpu::{
+    cache::{CacheConfig, CacheHierarchy},
+    code_coverage::CodeCoverage,
+    cpu::{
+        coverage::MicrocodeCoverage,
+        pipeline::{PipelineConfig, PipelineError, PipelinedCpu, PredictorKind},
+        tick_costs::TickCostTable,
+        ControlUnit, Cpu, ExecutionLimits, HaltReason, HistoryConfig, Snapshot, ERROR_EXIT_CODE,
+    },
+    dap::DapServer,
+    debugger::Debugger,
+    differential,
+    gdb::GdbStub,
+    io_controller::{IOController, SimpleInputOutput},
+    latency::{Latency, LatencyRegion, MemoryLatency},
+    memory::Memory,
+    multicore::{MultiCore, Schedule},
+    observer::{observed_tick, run_observed, TickObserver},
+    profiler::Profiler,
+    protection::MemoryProtection,
+    shadow_memory::ShadowMemory,
+    trace::{TraceConfig, TraceField, TraceLevel, TraceRecord, Tracer},
+    trace_diff::{read_trace, TraceDiff},
+    vcd::VcdWriter,
+    Simulator,
+};
+use isa::{CompiledProgram, DebugInfo};
 
 fn main() {
     match start() {
-        Ok(_) => (),
-        Err(err) => eprintln!("Error: {err}"),
+        Ok(exit_code) => process::exit(exit_code),
+        Err(err) => {
+            eprintln!("Error: {err}");
+            process::exit(ERROR_EXIT_CODE)
+        }
     }
 }
 
-fn start() -> Result<(), Box<dyn Error>> {
+/// Returns process exit status
+fn start() -> Result<i32, Box<dyn Error>> {
     let log_path = Path::new("cpu.log");
     let log_output = OpenOptions::new()
         .create(true)
@@ -42,29 +69,790 @@ fn start() -> Result<(), Box<dyn Error>> {
     log::info!("{}", delimiter);
     log::info!("{}", delimiter);
 
-    let config = parse_cli_args()?;
-    let program: CompiledProgram = serde_json::from_reader(File::open(config.program_path)?)?;
-    let output = fs::read_to_string(config.io_device_input_path)?;
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    check_empty_arguments(&args)?;
+
+    if take_flag(&mut args, "tick-costs") {
+        print_tick_costs(args)?;
+        return Ok(0);
+    }
+
+    if take_flag(&mut args, "batch") {
+        return run_batch(args);
+    }
+
+    if take_flag(&mut args, "debug") {
+        run_debugger(args)?;
+        return Ok(0);
+    }
+
+    if take_flag(&mut args, "dap") {
+        DapServer::new(io::stdin(), io::stdout()).serve()?;
+        return Ok(0);
+    }
+
+    if take_flag(&mut args, "gdb") {
+        run_gdb_stub(args)?;
+        return Ok(0);
+    }
+
+    if take_flag(&mut args, "code-coverage") {
+        print_code_coverage(args)?;
+        return Ok(0);
+    }
+
+    if take_flag(&mut args, "trace-diff") {
+        return run_trace_diff(args);
+    }
+
+    if take_flag(&mut args, "microcode-coverage") {
+        print_microcode_coverage(args)?;
+        return Ok(0);
+    }
+
+    let config = parse_cli_args(args)?;
+    let program: CompiledProgram = serde_json::from_reader(File::open(&config.program_path)?)?;
+    let output = fs::read_to_string(&config.io_device_input_path)?;
+    let memory_protection = config
+        .protect_memory
+        .then(|| MemoryProtection::new(&program, config.protect_low.unwrap_or_default()));
 
-    let memory = Memory::burn(program);
+    if config.cores > 1 {
+        let cores = (0..config.cores)
+            .map(|_| {
+                // every core has private caches
+                config.configure(
+                    Cpu::new(Memory::new(), IOController::new())
+                        .with_control_unit(config.control_unit.clone()),
+                    memory_protection.as_ref(),
+                )
+            })
+            .collect();
+        let mut system = MultiCore::new(
+            cores,
+            Memory::burn(program)?,
+            IOController::new().connect(0, Box::new(SimpleInputOutput::new(output))),
+            config.schedule,
+        )?;
+
+        let report = system.run();
+        if let Some(report_path) = config.report {
+            fs::write(report_path, report.to_json()?)?;
+        }
+
+        for (core, core_report) in report.cores.iter().enumerate() {
+            match &core_report.halt_reason {
+                HaltReason::Halt { .. } => (),
+                HaltReason::Fault(fault) => eprint!("Core {core}: {fault}"),
+                other => eprintln!("Core {core} stopped: {other}"),
+            }
+            for stats in &core_report.caches {
+                eprintln!("Core {core}: {stats}");
+            }
+        }
+
+        return Ok(report.exit_code());
+    }
+
+    if config.differential {
+        let microprogrammed = config.configure(
+            Cpu::new(
+                Memory::burn(program.clone())?,
+                IOController::new().connect(0, Box::new(SimpleInputOutput::new(output.clone()))),
+            ),
+            memory_protection.as_ref(),
+        );
+        let hardwired = config.configure(
+            Cpu::new(
+                Memory::burn(program)?,
+                IOController::new().connect(0, Box::new(SimpleInputOutput::new(output).silent())),
+            )
+            .with_control_unit(ControlUnit::hardwired()),
+            memory_protection.as_ref(),
+        );
+
+        return match differential::run_lock_step(microprogrammed, hardwired) {
+            Ok((ticks, halt_reason)) => {
+                println!();
+                println!("Control units agree for {ticks} ticks, {halt_reason}");
+                Ok(halt_reason.exit_code())
+            }
+            Err(divergence) => {
+                println!();
+                print!("{divergence}");
+                Ok(differential::DIVERGENCE_EXIT_CODE)
+            }
+        };
+    }
+
+    if let Some(pipeline_config) = config.pipeline {
+        let pipelined = PipelinedCpu::new(
+            Memory::burn(program.clone())?,
+            IOController::new().connect(0, Box::new(SimpleInputOutput::new(output.clone()))),
+            pipeline_config,
+        )
+        .with_limits(config.limits);
+        let pipeline_stats = match pipelined.start() {
+            Ok(pipeline_stats) => pipeline_stats,
+            Err(PipelineError::Stopped { reason, stats }) => {
+                println!();
+                println!("{stats}");
+                eprintln!("Stopped: {reason}");
+                return Ok(reason.exit_code());
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        // sequential model for reference, pipelined one ignores exit code of HALT
+        let sequential = Cpu::new(
+            Memory::burn(program)?,
+            IOController::new().connect(0, Box::new(SimpleInputOutput::new(output).silent())),
+        )
+        .without_tick_log()
+        .with_limits(config.limits)
+        .start();
+
+        println!();
+        println!("{pipeline_stats}");
+        println!(
+            "Sequential: {} instructions, {} ticks, TPI {:.2}",
+            sequential.counters.instructions,
+            sequential.counters.ticks,
+            sequential.counters.ticks_per_instruction()
+        );
+        match &sequential.halt_reason {
+            HaltReason::Halt { .. } => (),
+            HaltReason::Fault(fault) => eprint!("Sequential: {fault}"),
+            other => eprintln!("Sequential stopped: {other}"),
+        }
+
+        return Ok(sequential.halt_reason.exit_code());
+    }
+
+    let debug_info = match &config.debug_info {
+        Some(path) => serde_json::from_reader(File::open(path)?)?,
+        None => DebugInfo::default(),
+    };
+    let mut shadow_memory = config
+        .check_memory
+        .then(|| ShadowMemory::new(&program, debug_info.clone()));
+
+    let memory = Memory::burn(program)?;
     let io_controller = IOController::new().connect(0, Box::new(SimpleInputOutput::new(output)));
 
-    let cpu = Cpu::new(memory, io_controller);
-    cpu.start();
+    let mut cpu = config.configure(
+        Cpu::new(memory, io_controller).with_control_unit(config.control_unit.clone()),
+        memory_protection.as_ref(),
+    );
+    if config.microcode_coverage.is_some() {
+        cpu = cpu.with_microcode_coverage();
+    }
+
+    if let Some(snapshot_path) = &config.restore_snapshot {
+        let snapshot: Snapshot =
+            serde_json::from_reader(BufReader::new(File::open(snapshot_path)?))?;
+        cpu.restore(snapshot)?;
+    }
+
+    let mut tracer = match &config.trace {
+        Some(trace_path) => Some(Tracer::new(
+            BufWriter::new(File::create(trace_path)?),
+            config.trace_config,
+        )),
+        None => None,
+    };
+    let mut vcd = match &config.vcd {
+        Some(vcd_path) => Some(VcdWriter::new(BufWriter::new(File::create(vcd_path)?))),
+        None => None,
+    };
+    let mut profiler = config.profile.is_some().then(Profiler::new);
+    let mut code_coverage = config
+        .code_coverage
+        .is_some()
+        .then(|| CodeCoverage::new(cpu.memory()));
+
+    let mut observers: Vec<&mut dyn TickObserver> = Vec::new();
+    if let Some(tracer) = &mut tracer {
+        observers.push(tracer);
+    }
+    if let Some(vcd) = &mut vcd {
+        observers.push(vcd);
+    }
+    if let Some(profiler) = &mut profiler {
+        observers.push(profiler);
+    }
+    if let Some(code_coverage) = &mut code_coverage {
+        observers.push(code_coverage);
+    }
+    if let Some(shadow_memory) = &mut shadow_memory {
+        observers.push(shadow_memory);
+    }
+    let mut tick = |cpu: &mut Cpu| observed_tick(cpu, &mut observers);
+
+    // snapshot is taken once tick is reached, or when CPU stops
+    if let (Some(snapshot_path), Some(tick_number)) = (&config.save_snapshot, config.snapshot_at) {
+        while cpu.counters().ticks < tick_number && tick(&mut cpu)? {}
+        fs::write(snapshot_path, cpu.snapshot().to_json()?)?;
+    }
+
+    while tick(&mut cpu)? {}
+    let report = cpu.finish();
+
+    if let (Some(snapshot_path), None) = (&config.save_snapshot, config.snapshot_at) {
+        fs::write(snapshot_path, cpu.snapshot().to_json()?)?;
+    }
+
+    if let (Some(coverage_path), Some(run_coverage)) =
+        (config.microcode_coverage, cpu.microcode_coverage())
+    {
+        // accumulate across runs
+        let mut coverage = if coverage_path.exists() {
+            serde_json::from_reader(File::open(&coverage_path)?)?
+        } else {
+            MicrocodeCoverage::default()
+        };
+        coverage.merge(run_coverage)?;
+        fs::write(coverage_path, coverage.to_json()?)?;
+    }
+
+    if let (Some(coverage_path), Some(run_coverage)) = (config.code_coverage, code_coverage) {
+        // accumulate across runs
+        let mut coverage = if coverage_path.exists() {
+            serde_json::from_reader(File::open(&coverage_path)?)?
+        } else {
+            CodeCoverage::default()
+        };
+        coverage.merge(&run_coverage)?;
+        fs::write(coverage_path, coverage.to_json()?)?;
+    }
+
+    if let (Some(profile_path), Some(profiler)) = (config.profile, profiler) {
+        let profile = profiler.profile(&debug_info);
+        let content = match config.profile_format {
+            ProfileFormat::Text => profile.to_text(),
+            ProfileFormat::Json => profile.to_json()?,
+            ProfileFormat::Folded => profile.to_folded(),
+        };
+        fs::write(profile_path, content)?;
+    }
+
+    if let Some(report_path) = config.report {
+        fs::write(report_path, report.to_json()?)?;
+    }
+
+    match &report.halt_reason {
+        HaltReason::Halt { .. } => (),
+        HaltReason::Fault(fault) => eprint!("{fault}"),
+        other => eprintln!("Stopped: {other}"),
+    }
+    for stats in &report.caches {
+        eprintln!("{stats}");
+    }
+    if let Some(shadow_memory) = shadow_memory {
+        let warnings = shadow_memory.warnings();
+        eprintln!("Memory check: {} warning(s)", warnings.len());
+        for warning in warnings {
+            eprintln!("{warning}");
+        }
+    }
+
+    Ok(report.halt_reason.exit_code())
+}
+
+/// cpu batch <program> <input>... [--control-unit microcode|hardwired] [--format text|json] [limits]
+///
+/// Runs program against every input on the same simulator, resetting it in between.
+/// Returns exit code of the first run which did not exit with zero
+fn run_batch(mut args: Vec<String>) -> Result<i32, Box<dyn Error>> {
+    let control_unit = parse_control_unit(&mut args)?;
+    let limits = parse_limits(&mut args)?;
+    let json = match take_option(&mut args, "--format")?.as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(other) => {
+            return Err(ConfigurationError::InvalidValue {
+                argument_name: "--format".into(),
+                value: other.into(),
+            }
+            .into())
+        }
+    };
+
+    args.reverse();
+    let program_path = args.pop().ok_or(ConfigurationError::ArgumentNotFound {
+        argument_name: "program path".into(),
+    })?;
+    if args.is_empty() {
+        return Err(ConfigurationError::ArgumentNotFound {
+            argument_name: "io device input".into(),
+        }
+        .into());
+    }
+    args.reverse();
+
+    let program: CompiledProgram = serde_json::from_reader(File::open(program_path)?)?;
+    let mut simulator = Simulator::new(
+        Cpu::new(Memory::new(), IOController::new())
+            .without_tick_log()
+            .with_control_unit(control_unit)
+            .with_limits(limits),
+    );
+
+    let mut exit_code = 0;
+    for input_path in args {
+        let input = fs::read_to_string(&input_path)?;
+        simulator.reset_with_program(program.clone())?;
+        simulator.connect(0, Box::new(SimpleInputOutput::new(input).silent()));
+
+        let report = simulator.run();
+        if exit_code == 0 {
+            exit_code = report.halt_reason.exit_code();
+        }
+
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "input": input_path, "report": report })
+            );
+        } else {
+            println!("{input_path}: {}", report.halt_reason);
+            println!("{}", String::from_utf8_lossy(&report.output));
+        }
+    }
+
+    Ok(exit_code)
+}
+
+/// cpu debug <program> <input> [--debug-info <path>] [--control-unit microcode|hardwired] [limits]
+///
+/// Reads debugger commands from stdin until `quit`
+fn run_debugger(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let debug_info = match take_option(&mut args, "--debug-info")? {
+        Some(path) => serde_json::from_reader(File::open(path)?)?,
+        None => DebugInfo::default(),
+    };
+    let cpu = debuggee(args)?;
+
+    let simulator = Simulator::new(cpu).with_history(HistoryConfig::default());
+    let mut debugger = Debugger::new(simulator, debug_info);
+    debugger.repl(io::stdin().lock(), io::stdout())?;
+
+    Ok(())
+}
+
+/// cpu gdb <program> <input> [--port <port>] [--control-unit microcode|hardwired] [limits]
+///
+/// Serves single GDB connection on localhost, 1234 is default port
+fn run_gdb_stub(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let port = parse_number_option(&mut args, "--port")?.unwrap_or(1234);
+    let cpu = debuggee(args)?;
+
+    let mut stub = GdbStub::new(Simulator::new(cpu));
+    // bound before waiting is announced, so GDB may connect right away
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for GDB on 127.0.0.1:{port}");
+    let (stream, _) = listener.accept()?;
+    stub.serve(stream)?;
+
+    Ok(())
+}
+
+/// CPU with program and input given by remaining arguments, without tick log
+fn debuggee(mut args: Vec<String>) -> Result<Cpu, Box<dyn Error>> {
+    let control_unit = parse_control_unit(&mut args)?;
+    let limits = parse_limits(&mut args)?;
+
+    let [program_path, input_path] = args.as_slice() else {
+        return Err(ConfigurationError::ArgumentNotFound {
+            argument_name: "program path and io device input".into(),
+        }
+        .into());
+    };
+
+    let program: CompiledProgram = serde_json::from_reader(File::open(program_path)?)?;
+    let input = fs::read_to_string(input_path)?;
+
+    Ok(Cpu::new(
+        Memory::burn(program)?,
+        IOController::new().connect(0, Box::new(SimpleInputOutput::new(input))),
+    )
+    .without_tick_log()
+    .with_control_unit(control_unit)
+    .with_limits(limits))
+}
+
+/// cpu code-coverage <coverage.json>... --debug-info <path> [--source <path>] [--format text|lcov|cobertura|json]
+fn print_code_coverage(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let format = take_option(&mut args, "--format")?;
+    let source = take_option(&mut args, "--source")?;
+    let debug_info_path =
+        take_option(&mut args, "--debug-info")?.ok_or(ConfigurationError::ArgumentNotFound {
+            argument_name: "--debug-info".into(),
+        })?;
+    if args.is_empty() {
+        return Err(ConfigurationError::ArgumentNotFound {
+            argument_name: "coverage file".into(),
+        }
+        .into());
+    }
+
+    let debug_info: DebugInfo = serde_json::from_reader(File::open(debug_info_path)?)?;
+    let mut coverage = CodeCoverage::default();
+    for path in args {
+        let run_coverage: CodeCoverage = serde_json::from_reader(File::open(path)?)?;
+        coverage.merge(&run_coverage)?;
+    }
+
+    let source = source.as_deref();
+    match format.as_deref() {
+        None | Some("text") => print!("{}", coverage.to_text(&debug_info, source)),
+        Some("lcov") => print!("{}", coverage.to_lcov(&debug_info, source)),
+        Some("cobertura") => print!("{}", coverage.to_cobertura(&debug_info, source)),
+        Some("json") => println!("{}", coverage.to_json()?),
+        Some(other) => {
+            return Err(ConfigurationError::InvalidValue {
+                argument_name: "--format".into(),
+                value: other.into(),
+            }
+            .into())
+        }
+    }
+
+    Ok(())
+}
+
+/// cpu trace-diff <left.jsonl> <right.jsonl> [--ignore FIELD,...] [--context <records>]
+/// cpu trace-diff --rerun <left program> <right program> <input>
+///     [--left-control-unit microcode|hardwired] [--right-control-unit microcode|hardwired]
+///     [--trace-level tick|instruction] [--ignore FIELD,...] [--context <records>] [limits]
+///
+/// Reports the first record where traces diverge.
+/// Returns 1 if they do, 0 if they agree
+fn run_trace_diff(mut args: Vec<String>) -> Result<i32, Box<dyn Error>> {
+    let ignored = take_option(&mut args, "--ignore")?
+        .map(|fields| {
+            fields
+                .split(',')
+                .filter(|field| !field.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut trace_diff = TraceDiff::new().with_ignored(ignored);
+    if let Some(context) = parse_number_option(&mut args, "--context")? {
+        trace_diff = trace_diff.with_context(context);
+    }
+
+    let (left, right) = if take_flag(&mut args, "--rerun") {
+        let left_control_unit = parse_control_unit_option(&mut args, "--left-control-unit")?;
+        let right_control_unit = parse_control_unit_option(&mut args, "--right-control-unit")?;
+        let trace_config = parse_trace_config(&mut args)?;
+        let limits = parse_limits(&mut args)?;
+
+        let [left_program, right_program, input_path] = args.as_slice() else {
+            return Err(ConfigurationError::ArgumentNotFound {
+                argument_name: "two program paths and io device input".into(),
+            }
+            .into());
+        };
+        let input = fs::read_to_string(input_path)?;
+        let trace = |program_path: &String, control_unit: ControlUnit| {
+            rerun_trace(
+                program_path,
+                &input,
+                control_unit,
+                limits,
+                trace_config.clone(),
+            )
+        };
+
+        (
+            trace(left_program, left_control_unit)?,
+            trace(right_program, right_control_unit)?,
+        )
+    } else {
+        let [left_path, right_path] = args.as_slice() else {
+            return Err(ConfigurationError::ArgumentNotFound {
+                argument_name: "two trace paths".into(),
+            }
+            .into());
+        };
+
+        (
+            read_trace(BufReader::new(File::open(left_path)?))?,
+            read_trace(BufReader::new(File::open(right_path)?))?,
+        )
+    };
+
+    match trace_diff.first_divergence(&left, &right) {
+        Some(divergence) => {
+            print!("{divergence}");
+            Ok(1)
+        }
+        None => {
+            println!("Traces agree for {} records", left.len());
+            Ok(0)
+        }
+    }
+}
+
+/// Trace of program run without tick log
+fn rerun_trace(
+    program_path: &str,
+    input: &str,
+    control_unit: ControlUnit,
+    limits: ExecutionLimits,
+    trace_config: TraceConfig,
+) -> Result<Vec<TraceRecord>, Box<dyn Error>> {
+    let program: CompiledProgram = serde_json::from_reader(File::open(program_path)?)?;
+    let mut cpu = Cpu::new(
+        Memory::burn(program)?,
+        IOController::new().connect(
+            0,
+            Box::new(SimpleInputOutput::new(input.to_owned()).silent()),
+        ),
+    )
+    .without_tick_log()
+    .with_control_unit(control_unit)
+    .with_limits(limits);
+
+    let mut tracer = Tracer::new(Vec::new(), trace_config);
+    run_observed(&mut cpu, &mut [&mut tracer])?;
+
+    Ok(read_trace(tracer.into_output().as_slice())?)
+}
+
+/// cpu microcode-coverage <coverage.json>... [--format text|json]
+fn print_microcode_coverage(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let format = take_option(&mut args, "--format")?;
+    if args.is_empty() {
+        return Err(ConfigurationError::ArgumentNotFound {
+            argument_name: "coverage file".into(),
+        }
+        .into());
+    }
+
+    let mut coverage = MicrocodeCoverage::default();
+    for path in args {
+        let run_coverage: MicrocodeCoverage = serde_json::from_reader(File::open(path)?)?;
+        coverage.merge(&run_coverage)?;
+    }
+
+    match format.as_deref() {
+        None | Some("text") => print!("{}", coverage.to_text()),
+        Some("json") => println!("{}", coverage.to_json()?),
+        Some(other) => {
+            return Err(ConfigurationError::InvalidValue {
+                argument_name: "--format".into(),
+                value: other.into(),
+            }
+            .into())
+        }
+    }
 
     Ok(())
 }
 
+/// cpu tick-costs [--control-unit microcode|hardwired] [--format text|json|markdown]
+fn print_tick_costs(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let control_unit = parse_control_unit(&mut args)?;
+    let table = TickCostTable::new(control_unit);
+
+    match take_option(&mut args, "--format")?.as_deref() {
+        None | Some("text") => print!("{}", table.to_text()),
+        Some("markdown") => print!("{}", table.to_markdown()),
+        Some("json") => println!("{}", table.to_json()?),
+        Some(other) => {
+            return Err(ConfigurationError::InvalidValue {
+                argument_name: "--format".into(),
+                value: other.into(),
+            }
+            .into())
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_control_unit(args: &mut Vec<String>) -> Result<ControlUnit, ConfigurationError> {
+    parse_control_unit_option(args, "--control-unit")
+}
+
+/// microcode|hardwired, microcode by default
+fn parse_control_unit_option(
+    args: &mut Vec<String>,
+    option: &str,
+) -> Result<ControlUnit, ConfigurationError> {
+    match take_option(args, option)?.as_deref() {
+        None | Some("microcode") => Ok(ControlUnit::microprogrammed()),
+        Some("hardwired") => Ok(ControlUnit::hardwired()),
+        Some(other) => Err(ConfigurationError::InvalidValue {
+            argument_name: option.into(),
+            value: other.into(),
+        }),
+    }
+}
+
 struct Config {
     program_path: PathBuf,
     io_device_input_path: PathBuf,
+    control_unit: ControlUnit,
+    /// run microprogrammed and hardwired control units lock-step
+    differential: bool,
+    /// run pipelined model instead of microcode
+    pipeline: Option<PipelineConfig>,
+    /// file to accumulate microcode coverage into
+    microcode_coverage: Option<PathBuf>,
+    /// file to write run report into
+    report: Option<PathBuf>,
+    limits: ExecutionLimits,
+    save_snapshot: Option<PathBuf>,
+    /// tick to save snapshot at. CPU stop by default
+    snapshot_at: Option<usize>,
+    restore_snapshot: Option<PathBuf>,
+    /// cores sharing memory and devices
+    cores: usize,
+    schedule: Schedule,
+    /// unified cache, takes precedence over separate ones
+    cache: Option<CacheConfig>,
+    instruction_cache: Option<CacheConfig>,
+    data_cache: Option<CacheConfig>,
+    memory_latency: MemoryLatency,
+    /// file to write JSON Lines trace into
+    trace: Option<PathBuf>,
+    trace_config: TraceConfig,
+    /// file to write Value Change Dump into
+    vcd: Option<PathBuf>,
+    /// file to write execution profile into
+    profile: Option<PathBuf>,
+    profile_format: ProfileFormat,
+    /// labels for profile and memory check
+    debug_info: Option<PathBuf>,
+    /// file to accumulate coverage of program into
+    code_coverage: Option<PathBuf>,
+    /// warn about uninitialized reads and stray writes
+    check_memory: bool,
+    /// fault on access regions of program do not permit
+    protect_memory: bool,
+    /// cells from zero closed for reads and writes
+    protect_low: Option<usize>,
+}
+
+enum ProfileFormat {
+    Text,
+    Json,
+    /// folded stacks for flamegraph tools
+    Folded,
+}
+
+impl Config {
+    fn caches(&self) -> Option<CacheHierarchy> {
+        match (self.cache, self.instruction_cache, self.data_cache) {
+            (Some(cache), ..) => Some(CacheHierarchy::unified(cache)),
+            (None, None, None) => None,
+            (None, instruction, data) => Some(CacheHierarchy::split(instruction, data)),
+        }
+    }
+
+    /// Limits, memory timing, caches and protection every CPU of the run shares
+    fn configure(&self, cpu: Cpu, memory_protection: Option<&MemoryProtection>) -> Cpu {
+        let mut cpu = cpu
+            .with_limits(self.limits)
+            .with_memory_latency(self.memory_latency.clone());
+        if let Some(caches) = self.caches() {
+            cpu = cpu.with_caches(caches);
+        }
+        if let Some(memory_protection) = memory_protection {
+            cpu = cpu.with_memory_protection(memory_protection.clone());
+        }
+
+        cpu
+    }
 }
 
 // custom parsing, because parsing of file paths is required
 // custom error handling logic is easier to implement in that way
-fn parse_cli_args() -> Result<Config, ConfigurationError> {
-    let mut args: Vec<String> = env::args().skip(1).collect();
-    check_empty_arguments(&args)?;
+fn parse_cli_args(mut args: Vec<String>) -> Result<Config, ConfigurationError> {
+    let differential = take_flag(&mut args, "--differential");
+    let control_unit = parse_control_unit(&mut args)?;
+
+    let pipeline = take_flag(&mut args, "--pipeline");
+    let forwarding = !take_flag(&mut args, "--no-forwarding");
+    let predictor = match take_option(&mut args, "--predictor")?.as_deref() {
+        None | Some("bimodal") => PredictorKind::Bimodal,
+        Some("not-taken") => PredictorKind::NotTaken,
+        Some(other) => {
+            return Err(ConfigurationError::InvalidValue {
+                argument_name: "--predictor".into(),
+                value: other.into(),
+            })
+        }
+    };
+    let pipeline = pipeline.then_some(PipelineConfig {
+        forwarding,
+        predictor,
+    });
+    let microcode_coverage = take_option(&mut args, "--microcode-coverage")?.map(PathBuf::from);
+    let report = take_option(&mut args, "--report")?.map(PathBuf::from);
+    let limits = parse_limits(&mut args)?;
+    let save_snapshot = take_option(&mut args, "--save-snapshot")?.map(PathBuf::from);
+    let snapshot_at = parse_number_option(&mut args, "--snapshot-at")?;
+    let restore_snapshot = take_option(&mut args, "--restore-snapshot")?.map(PathBuf::from);
+    let cores = parse_number_option(&mut args, "--cores")?.unwrap_or(1);
+    if cores == 0 {
+        return Err(ConfigurationError::InvalidValue {
+            argument_name: "--cores".into(),
+            value: cores.to_string(),
+        });
+    }
+    let schedule = parse_schedule(&mut args)?;
+    let cache = parse_cache_option(&mut args, "--cache")?;
+    let instruction_cache = parse_cache_option(&mut args, "--icache")?;
+    let data_cache = parse_cache_option(&mut args, "--dcache")?;
+    let memory_latency = parse_memory_latency(&mut args)?;
+    let trace = take_option(&mut args, "--trace")?.map(PathBuf::from);
+    let trace_config = parse_trace_config(&mut args)?;
+    let vcd = take_option(&mut args, "--vcd")?.map(PathBuf::from);
+    let profile = take_option(&mut args, "--profile")?.map(PathBuf::from);
+    let profile_format = match take_option(&mut args, "--profile-format")?.as_deref() {
+        None | Some("text") => ProfileFormat::Text,
+        Some("json") => ProfileFormat::Json,
+        Some("folded") => ProfileFormat::Folded,
+        Some(other) => {
+            return Err(ConfigurationError::InvalidValue {
+                argument_name: "--profile-format".into(),
+                value: other.into(),
+            })
+        }
+    };
+    let debug_info = take_option(&mut args, "--debug-info")?.map(PathBuf::from);
+    let code_coverage = take_option(&mut args, "--code-coverage")?.map(PathBuf::from);
+    let check_memory = take_flag(&mut args, "--check-memory");
+    let protect_low = parse_number_option(&mut args, "--protect-low")?;
+    // protecting low memory implies protection of the rest
+    let protect_memory = take_flag(&mut args, "--protect-memory") || protect_low.is_some();
+
+    // cores run without observers, snapshots and other models
+    if cores > 1 {
+        let unsupported = [
+            ("--trace", trace.is_some()),
+            ("--vcd", vcd.is_some()),
+            ("--profile", profile.is_some()),
+            ("--code-coverage", code_coverage.is_some()),
+            ("--check-memory", check_memory),
+            ("--save-snapshot", save_snapshot.is_some()),
+            ("--restore-snapshot", restore_snapshot.is_some()),
+            ("--microcode-coverage", microcode_coverage.is_some()),
+            ("--differential", differential),
+            ("--pipeline", pipeline.is_some()),
+        ];
+        if let Some((other, _)) = unsupported.into_iter().find(|&(_, given)| given) {
+            return Err(ConfigurationError::IncompatibleArguments {
+                argument_name: "--cores".into(),
+                other: other.into(),
+            });
+        }
+    }
 
     args.reverse();
 
@@ -85,5 +873,164 @@ fn parse_cli_args() -> Result<Config, ConfigurationError> {
     Ok(Config {
         program_path,
         io_device_input_path,
+        control_unit,
+        differential,
+        pipeline,
+        microcode_coverage,
+        report,
+        limits,
+        save_snapshot,
+        snapshot_at,
+        restore_snapshot,
+        cores,
+        schedule,
+        cache,
+        instruction_cache,
+        data_cache,
+        memory_latency,
+        trace,
+        trace_config,
+        vcd,
+        profile,
+        profile_format,
+        debug_info,
+        code_coverage,
+        check_memory,
+        protect_memory,
+        protect_low,
     })
 }
+
+/// --trace-level tick|instruction --trace-fields FIELD,FIELD,...
+fn parse_trace_config(args: &mut Vec<String>) -> Result<TraceConfig, ConfigurationError> {
+    let mut trace_config = TraceConfig::default();
+
+    if let Some(level) = take_option(args, "--trace-level")? {
+        trace_config.level = TraceLevel::parse(&level).ok_or(ConfigurationError::InvalidValue {
+            argument_name: "--trace-level".into(),
+            value: level,
+        })?;
+    }
+
+    if let Some(fields) = take_option(args, "--trace-fields")? {
+        trace_config.fields = fields
+            .split(',')
+            .filter(|field| !field.is_empty())
+            .map(TraceField::parse)
+            .collect::<Option<_>>()
+            .ok_or(ConfigurationError::InvalidValue {
+                argument_name: "--trace-fields".into(),
+                value: fields,
+            })?;
+    }
+
+    Ok(trace_config)
+}
+
+/// --wait-states read=R,write=W and any number of --wait-region START-END:read=R,write=W
+fn parse_memory_latency(args: &mut Vec<String>) -> Result<MemoryLatency, ConfigurationError> {
+    let invalid = |option: &str, value: String| ConfigurationError::InvalidValue {
+        argument_name: option.into(),
+        value,
+    };
+
+    let mut memory_latency = MemoryLatency::default();
+    if let Some(spec) = take_option(args, "--wait-states")? {
+        memory_latency.default = Latency::parse(&spec, Latency::default())
+            .map_err(|_| invalid("--wait-states", spec))?;
+    }
+
+    // regions are applied in order of appearance
+    while let Some(spec) = take_option(args, "--wait-region")? {
+        let region = LatencyRegion::parse(&spec, memory_latency.default)
+            .map_err(|_| invalid("--wait-region", spec))?;
+        memory_latency.regions.push(region);
+    }
+
+    Ok(memory_latency)
+}
+
+fn parse_cache_option(
+    args: &mut Vec<String>,
+    option: &str,
+) -> Result<Option<CacheConfig>, ConfigurationError> {
+    take_option(args, option)?
+        .map(|spec| {
+            CacheConfig::parse(&spec).map_err(|_| ConfigurationError::InvalidValue {
+                argument_name: option.into(),
+                value: spec,
+            })
+        })
+        .transpose()
+}
+
+/// round-robin[:QUANTUM] | pattern:CORE,CORE,... | seeded:SEED
+fn parse_schedule(args: &mut Vec<String>) -> Result<Schedule, ConfigurationError> {
+    let Some(value) = take_option(args, "--schedule")? else {
+        return Ok(Schedule::default());
+    };
+
+    let (kind, parameter) = value.split_once(':').unwrap_or((&value, ""));
+    let schedule = match (kind, parameter) {
+        ("round-robin", "") => Some(Schedule::default()),
+        ("round-robin", quantum) => quantum
+            .parse()
+            .ok()
+            .filter(|&quantum| quantum > 0)
+            .map(|quantum| Schedule::RoundRobin { quantum }),
+        ("pattern", pattern) => pattern
+            .split(',')
+            .map(|core| core.trim().parse().ok())
+            .collect::<Option<_>>()
+            .map(Schedule::Pattern),
+        ("seeded", seed) => seed.parse().ok().map(Schedule::Seeded),
+        _ => None,
+    };
+
+    schedule.ok_or(ConfigurationError::InvalidValue {
+        argument_name: "--schedule".into(),
+        value,
+    })
+}
+
+fn parse_limits(args: &mut Vec<String>) -> Result<ExecutionLimits, ConfigurationError> {
+    Ok(ExecutionLimits {
+        max_ticks: parse_number_option(args, "--max-ticks")?,
+        max_instructions: parse_number_option(args, "--max-instructions")?,
+        max_time: parse_duration_option(args, "--max-time")?,
+        detect_loops: take_flag(args, "--detect-loops"),
+    })
+}
+
+/// Seconds, fractions allowed. Negative, infinite and NaN are invalid
+fn parse_duration_option(
+    args: &mut Vec<String>,
+    option: &str,
+) -> Result<Option<Duration>, ConfigurationError> {
+    take_option(args, option)?
+        .map(|value| {
+            value
+                .parse()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .ok_or(ConfigurationError::InvalidValue {
+                    argument_name: option.into(),
+                    value,
+                })
+        })
+        .transpose()
+}
+
+fn parse_number_option<T: FromStr>(
+    args: &mut Vec<String>,
+    option: &str,
+) -> Result<Option<T>, ConfigurationError> {
+    take_option(args, option)?
+        .map(|value| {
+            value.parse().map_err(|_| ConfigurationError::InvalidValue {
+                argument_name: option.into(),
+                value,
+            })
+        })
+        .transpose()
+}
@@ -1,9 +1,44 @@
-use std::{fmt::Debug, ops::{Index, IndexMut}};
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    ops::{Index, IndexMut},
+};
 
 use isa::{CompiledProgram, CompiledSection, MemoryItem, RawAddress, MEMORY_SIZE};
+use serde::{Deserialize, Serialize};
 
 type TMemory = Vec<MemoryItem>;
 
+/// Cells which differ from empty memory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemoryState {
+    pub cells: Vec<(RawAddress, MemoryItem)>,
+}
+
+#[derive(Debug)]
+pub enum MemoryError {
+    SectionOutOfMemory {
+        start_address: RawAddress,
+        length: usize,
+    },
+}
+
+impl Error for MemoryError {}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryError::SectionOutOfMemory {
+                start_address,
+                length,
+            } => writeln!(
+                f,
+                "Section of {length} items at {start_address:#06x} does not fit into memory of {MEMORY_SIZE} cells"
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Memory(TMemory);
 
@@ -35,6 +70,12 @@ impl IndexMut<usize> for Memory {
     }
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Memory {
     /// Creates empty memory
     pub fn new() -> Self {
@@ -43,15 +84,34 @@ impl Memory {
         Self(memory)
     }
 
+    pub fn state(&self) -> MemoryState {
+        MemoryState {
+            cells: self
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(_, &item)| item != MemoryItem::Data(0))
+                .map(|(address, &item)| (address as RawAddress, item))
+                .collect(),
+        }
+    }
+
+    pub fn restore(&mut self, MemoryState { cells }: MemoryState) {
+        self.0.fill(MemoryItem::Data(0));
+        for (address, item) in cells {
+            self[address] = item;
+        }
+    }
+
     /// Creates new memory and burns program into it
-    pub fn burn(CompiledProgram { sections }: CompiledProgram) -> Memory {
+    pub fn burn(CompiledProgram { sections }: CompiledProgram) -> Result<Memory, MemoryError> {
         let mut memory: Memory = Self::new();
 
         for section in sections {
-            memory.burn_section(section);
+            memory.burn_section(section)?;
         }
 
-        memory
+        Ok(memory)
     }
 
     fn burn_section(
@@ -59,11 +119,20 @@ impl Memory {
         CompiledSection {
             start_address,
             items,
+            ..
         }: CompiledSection,
-    ) {
-        // splice *inserts* elements rather than replace
-        for (offset, item) in items.iter().enumerate() {
-            self[start_address + offset as u16] = *item;
+    ) -> Result<(), MemoryError> {
+        let start = start_address as usize;
+        if start + items.len() > MEMORY_SIZE {
+            return Err(MemoryError::SectionOutOfMemory {
+                start_address,
+                length: items.len(),
+            });
         }
+
+        // splice *inserts* elements rather than replace
+        self.0[start..start + items.len()].copy_from_slice(&items);
+
+        Ok(())
     }
 }
@@ -0,0 +1,269 @@
+//! Several cores sharing one memory and one set of devices.
+//! Cores are interleaved tick by tick in order given by schedule,
+//! so every run of the same program under the same schedule is identical.
+//! Shared memory and devices are handed to the core for the duration
+//! of its tick.
+
+use std::{error::Error, fmt::Display, mem};
+
+use serde::Serialize;
+
+use crate::{
+    cpu::{Cpu, HaltReason, RunReport},
+    io_controller::IOController,
+    memory::Memory,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// every core runs `quantum` ticks in turn
+    RoundRobin { quantum: usize },
+    /// core indices, repeated over and over
+    Pattern(Vec<usize>),
+    /// pseudo-random order, reproducible from seed
+    Seeded(u64),
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::RoundRobin { quantum: 1 }
+    }
+}
+
+#[derive(Debug)]
+pub enum MultiCoreError {
+    NoCores,
+    EmptySchedule,
+    UnknownCore { core: usize, cores: usize },
+}
+
+impl Error for MultiCoreError {}
+
+impl Display for MultiCoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultiCoreError::NoCores => writeln!(f, "System needs at least one core"),
+            MultiCoreError::EmptySchedule => writeln!(f, "Schedule pattern is empty"),
+            MultiCoreError::UnknownCore { core, cores } => {
+                writeln!(
+                    f,
+                    "Schedule refers to core {core}, but there are {cores} cores"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MultiCoreReport {
+    /// output of every core holds bytes this core wrote
+    pub cores: Vec<RunReport>,
+    /// everything written to devices, in order
+    pub output: Vec<u8>,
+    pub ticks: usize,
+}
+
+impl MultiCoreReport {
+    /// Exit code of the first core which did not exit with zero
+    pub fn exit_code(&self) -> i32 {
+        self.cores
+            .iter()
+            .map(|core| core.halt_reason.exit_code())
+            .find(|&exit_code| exit_code != 0)
+            .unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct MultiCore {
+    cores: Vec<Cpu>,
+    memory: Memory,
+    io_controller: IOController,
+    schedule: Schedule,
+    // scheduling decisions made so far
+    position: usize,
+    // state of xorshift generator of seeded schedule
+    random: u64,
+    // core which executed previous tick
+    last_core: Option<usize>,
+    // bytes written by every core
+    outputs: Vec<Vec<u8>>,
+    ticks: usize,
+}
+
+impl MultiCore {
+    /// Every core gets id equal to its index. Memory and devices
+    /// cores were built with are replaced by shared ones
+    pub fn new(
+        cores: Vec<Cpu>,
+        memory: Memory,
+        io_controller: IOController,
+        schedule: Schedule,
+    ) -> Result<Self, MultiCoreError> {
+        if cores.is_empty() {
+            return Err(MultiCoreError::NoCores);
+        }
+
+        if let Schedule::Pattern(pattern) = &schedule {
+            if pattern.is_empty() {
+                return Err(MultiCoreError::EmptySchedule);
+            }
+            if let Some(&core) = pattern.iter().find(|&&core| core >= cores.len()) {
+                return Err(MultiCoreError::UnknownCore {
+                    core,
+                    cores: cores.len(),
+                });
+            }
+        }
+
+        let random = match schedule {
+            // xorshift never leaves zero state
+            Schedule::Seeded(seed) => seed.max(1),
+            _ => 0,
+        };
+
+        Ok(Self {
+            outputs: vec![Vec::new(); cores.len()],
+            cores: cores
+                .into_iter()
+                .enumerate()
+                .map(|(index, core)| core.with_core_id(index as u32))
+                .collect(),
+            memory,
+            io_controller,
+            schedule,
+            position: 0,
+            random,
+            last_core: None,
+            ticks: 0,
+        })
+    }
+
+    /// Executes single tick of one core. Returns `false` when all cores stopped
+    pub fn tick(&mut self) -> bool {
+        let Some(index) = self.next_core() else {
+            return false;
+        };
+
+        // registers of this core repeating says nothing
+        // if other cores ran in between: they could change memory
+        if self.last_core.is_some_and(|last_core| last_core != index) {
+            self.cores[index].forget_seen_states();
+        }
+        self.last_core = Some(index);
+
+        let core = &mut self.cores[index];
+        let written = self.io_controller.written().len();
+
+        mem::swap(core.memory_mut(), &mut self.memory);
+        mem::swap(core.io_controller_mut(), &mut self.io_controller);
+        core.tick();
+        mem::swap(core.memory_mut(), &mut self.memory);
+        mem::swap(core.io_controller_mut(), &mut self.io_controller);
+
+        self.outputs[index].extend_from_slice(&self.io_controller.written()[written..]);
+        self.ticks += 1;
+
+        true
+    }
+
+    /// Runs until all cores stop
+    pub fn run(&mut self) -> MultiCoreReport {
+        while self.tick() {}
+
+        for core in &self.cores {
+            let counters = core.counters();
+            log::info!(
+                "Core {}: Instructions: {}; MC: {}",
+                core.core_id(),
+                counters.instructions,
+                counters.ticks
+            );
+        }
+
+        self.report()
+    }
+
+    /// Report on stopped system
+    pub fn report(&self) -> MultiCoreReport {
+        MultiCoreReport {
+            cores: self
+                .cores
+                .iter()
+                .zip(&self.outputs)
+                .map(|(core, output)| RunReport {
+                    output: output.clone(),
+                    ..core.report()
+                })
+                .collect(),
+            output: self.io_controller.written().to_vec(),
+            ticks: self.ticks,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.cores.iter().all(Cpu::is_halted)
+    }
+
+    pub fn halt_reasons(&self) -> impl Iterator<Item = Option<&HaltReason>> {
+        self.cores.iter().map(Cpu::halt_reason)
+    }
+
+    pub fn cores(&self) -> &[Cpu] {
+        &self.cores
+    }
+
+    pub fn core_mut(&mut self, index: usize) -> Option<&mut Cpu> {
+        self.cores.get_mut(index)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn io_controller(&self) -> &IOController {
+        &self.io_controller
+    }
+
+    pub fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    /// Core holding the bus runs until its atomic instruction completes.
+    /// Otherwise schedule picks the core. Stopped core passes its turn
+    /// to the next running one
+    fn next_core(&mut self) -> Option<usize> {
+        if let Some(index) = self.cores.iter().position(Cpu::holds_bus) {
+            return Some(index);
+        }
+
+        if self.is_halted() {
+            return None;
+        }
+
+        let cores = self.cores.len();
+        let scheduled = match &self.schedule {
+            Schedule::RoundRobin { quantum } => self.position / quantum.max(&1) % cores,
+            Schedule::Pattern(pattern) => pattern[self.position % pattern.len()],
+            Schedule::Seeded(_) => {
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 7;
+                self.random ^= self.random << 17;
+                (self.random % cores as u64) as usize
+            }
+        };
+        self.position += 1;
+
+        (0..cores)
+            .map(|offset| (scheduled + offset) % cores)
+            .find(|&index| !self.cores[index].is_halted())
+    }
+}
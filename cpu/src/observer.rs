@@ -0,0 +1,151 @@
+//! Hook into tick loop for tools which watch execution from outside,
+//! e.g. trace and waveform dump
+
+use std::io;
+
+use isa::{MemoryItem, RawAddress, RawPort};
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::{Cpu, ExecutedInstruction, Microinstruction, Registers, Signal, Status};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: RawAddress,
+    pub write: bool,
+    pub value: MemoryItem,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IoTransaction {
+    pub port: RawPort,
+    pub write: bool,
+    pub value: u8,
+}
+
+/// Executed tick. Registers and status are the ones before it,
+/// CPU passed along holds the ones after
+#[derive(Debug, Clone)]
+pub struct TickEvent {
+    /// counting from 0
+    pub tick: usize,
+    /// instruction tick belongs to, counting from 0
+    pub instruction: usize,
+    /// `None` for hardwired control unit
+    pub micro_pc: Option<usize>,
+    /// signals issued. Empty when tick was spent waiting for memory
+    pub micro_instruction: Microinstruction,
+    pub stall: bool,
+    /// instruction fetch was in progress: memory read is instruction read
+    pub fetching: bool,
+    pub registers: Registers,
+    pub status: Status,
+    pub memory: Vec<MemoryAccess>,
+    pub io: Vec<IoTransaction>,
+    /// instruction which completed with this tick: next fetch follows
+    pub completed: Option<ExecutedInstruction>,
+}
+
+pub trait TickObserver {
+    fn observe(&mut self, event: &TickEvent, cpu: &Cpu) -> io::Result<()>;
+
+    /// CPU stopped. `last` is instruction it stopped on: it never
+    /// completes, but was executed. `None` when CPU stopped during fetch
+    fn finish(&mut self, _last: Option<ExecutedInstruction>, _cpu: &Cpu) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Same as `Cpu::tick`, but every observer sees executed tick
+pub fn observed_tick(cpu: &mut Cpu, observers: &mut [&mut dyn TickObserver]) -> io::Result<bool> {
+    let was_halted = cpu.is_halted();
+    let mut micro_instruction = cpu.current_microinstruction();
+    let micro_pc = cpu.control_unit().microcode_program_counter();
+    let fetching = cpu.is_fetching();
+    let registers = cpu.registers().clone();
+    let status = cpu.status();
+    let counters = cpu.counters();
+
+    let running = cpu.tick();
+
+    // CPU may stop without executing tick
+    if cpu.counters().ticks != counters.ticks {
+        let stall = cpu.counters().stall_ticks != counters.stall_ticks;
+        if stall {
+            micro_instruction.clear();
+        }
+        let after = cpu.registers();
+
+        let mut memory = Vec::new();
+        if micro_instruction.contains(&Signal::SELECT_MEM)
+            && micro_instruction.contains(&Signal::WRITE_DATA)
+        {
+            memory.push(MemoryAccess {
+                address: registers.address,
+                write: false,
+                value: after.data,
+            });
+        }
+        if micro_instruction.contains(&Signal::WRITE_MEM) {
+            memory.push(MemoryAccess {
+                address: registers.address,
+                write: true,
+                value: registers.data,
+            });
+        }
+
+        let mut io = Vec::new();
+        // data register holds port during IO
+        let port = registers.data.data().map(|port| port as RawPort);
+        if let Some(port) = port.filter(|_| micro_instruction.contains(&Signal::IO)) {
+            if micro_instruction.contains(&Signal::WRITE_IO) {
+                io.push(IoTransaction {
+                    port,
+                    write: true,
+                    value: registers.accumulator as u8,
+                });
+            }
+            if micro_instruction.contains(&Signal::WRITE_ACCUMULATOR) {
+                io.push(IoTransaction {
+                    port,
+                    write: false,
+                    value: after.accumulator as u8,
+                });
+            }
+        }
+
+        let event = TickEvent {
+            tick: counters.ticks,
+            instruction: counters.instructions,
+            micro_pc,
+            micro_instruction,
+            stall,
+            fetching,
+            registers,
+            status,
+            memory,
+            io,
+            completed: cpu
+                .last_instruction()
+                .filter(|_| !stall && cpu.control_unit().is_fetching_instruction()),
+        };
+        for observer in observers.iter_mut() {
+            observer.observe(&event, cpu)?;
+        }
+    }
+
+    if !running && !was_halted {
+        let last = cpu.last_instruction().filter(|_| !cpu.is_fetching());
+        for observer in observers.iter_mut() {
+            observer.finish(last, cpu)?;
+        }
+    }
+
+    Ok(running)
+}
+
+/// Same as `Cpu::run` without final report, but observed
+pub fn run_observed(cpu: &mut Cpu, observers: &mut [&mut dyn TickObserver]) -> io::Result<()> {
+    while observed_tick(cpu, observers)? {}
+
+    Ok(())
+}
@@ -0,0 +1,319 @@
+//! Execution profiler: where ticks go. Instructions and ticks are counted
+//! per instruction address, then summed up per label, opcode and operand
+//! type. Taken backward jumps mark loops
+
+use std::{collections::BTreeMap, fmt::Write, io};
+
+use isa::{CompiledCommand, DebugInfo, RawAddress};
+use serde::Serialize;
+
+use crate::{
+    cpu::{Cpu, ExecutedInstruction},
+    debugger::disassemble_command,
+    observer::{TickEvent, TickObserver},
+};
+
+/// Rows shown in every table of text report
+const TOP: usize = 10;
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub instructions: usize,
+    /// ticks spent waiting for memory included
+    pub ticks: usize,
+}
+
+impl Cost {
+    pub fn ticks_per_instruction(&self) -> f64 {
+        self.ticks as f64 / self.instructions.max(1) as f64
+    }
+
+    fn add(&mut self, other: Cost) {
+        self.instructions += other.instructions;
+        self.ticks += other.ticks;
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct InstructionProfile {
+    pub address: RawAddress,
+    /// nearest label at or before instruction
+    pub label: Option<String>,
+    pub offset: RawAddress,
+    pub instruction: String,
+    pub command: CompiledCommand,
+    pub cost: Cost,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NamedCost {
+    pub name: String,
+    pub cost: Cost,
+}
+
+/// Taken backward jump from `end` to `start`
+#[derive(Serialize, Debug, Clone)]
+pub struct LoopProfile {
+    pub start: RawAddress,
+    pub end: RawAddress,
+    pub label: Option<String>,
+    pub iterations: usize,
+    /// of instructions between `start` and `end`,
+    /// including ones of other loops nested into it
+    pub cost: Cost,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Profile {
+    pub total: Cost,
+    /// hottest first, as are all lists below
+    pub instructions: Vec<InstructionProfile>,
+    /// instructions before the first label are counted under `<none>`
+    pub labels: Vec<NamedCost>,
+    pub loops: Vec<LoopProfile>,
+    pub opcodes: Vec<NamedCost>,
+    pub operand_types: Vec<NamedCost>,
+}
+
+/// Collects costs while CPU runs
+#[derive(Debug, Default)]
+pub struct Profiler {
+    costs: BTreeMap<RawAddress, (CompiledCommand, Cost)>,
+    // (start, end) of loop to iterations
+    back_jumps: BTreeMap<(RawAddress, RawAddress), usize>,
+    // ticks of instruction in progress
+    ticks: usize,
+    previous: Option<RawAddress>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Costs so far. Labels come from debug info
+    pub fn profile(&self, debug_info: &DebugInfo) -> Profile {
+        let mut total = Cost::default();
+        let mut labels: BTreeMap<String, Cost> = BTreeMap::new();
+        let mut opcodes: BTreeMap<String, Cost> = BTreeMap::new();
+        let mut operand_types: BTreeMap<String, Cost> = BTreeMap::new();
+
+        let mut instructions = Vec::new();
+        for (&address, &(command, cost)) in &self.costs {
+            total.add(cost);
+
+            let label = debug_info.nearest_label(address);
+            let label_name = label.map_or("<none>", |(label, _)| label);
+            labels.entry(label_name.to_owned()).or_default().add(cost);
+            opcodes
+                .entry(command.opcode.to_string().to_lowercase())
+                .or_default()
+                .add(cost);
+            operand_types
+                .entry(format!("{:?}", command.operand.operand_type).to_lowercase())
+                .or_default()
+                .add(cost);
+
+            instructions.push(InstructionProfile {
+                address,
+                label: label.map(|(label, _)| label.to_owned()),
+                offset: label.map_or(address, |(_, offset)| offset),
+                instruction: disassemble_command(command, address, debug_info),
+                command,
+                cost,
+            });
+        }
+        instructions.sort_by_key(|instruction| std::cmp::Reverse(instruction.cost.ticks));
+
+        let mut loops: Vec<LoopProfile> = self
+            .back_jumps
+            .iter()
+            .map(|(&(start, end), &iterations)| {
+                let mut cost = Cost::default();
+                for (_, (_, instruction_cost)) in self.costs.range(start..=end) {
+                    cost.add(*instruction_cost);
+                }
+
+                LoopProfile {
+                    start,
+                    end,
+                    label: debug_info.nearest_label(start).map(format_label),
+                    iterations,
+                    cost,
+                }
+            })
+            .collect();
+        loops.sort_by_key(|profile| std::cmp::Reverse(profile.cost.ticks));
+
+        Profile {
+            total,
+            instructions,
+            labels: sorted(labels),
+            loops,
+            opcodes: sorted(opcodes),
+            operand_types: sorted(operand_types),
+        }
+    }
+
+    /// Instruction completed: its ticks are attributed to it
+    fn complete(&mut self, ExecutedInstruction { address, command }: ExecutedInstruction) {
+        let ticks = std::mem::take(&mut self.ticks);
+
+        let (_, cost) = self
+            .costs
+            .entry(address)
+            .or_insert((command, Cost::default()));
+        cost.add(Cost {
+            instructions: 1,
+            ticks,
+        });
+
+        if let Some(previous) = self.previous.filter(|&previous| address <= previous) {
+            *self.back_jumps.entry((address, previous)).or_default() += 1;
+        }
+        self.previous = Some(address);
+    }
+}
+
+impl TickObserver for Profiler {
+    fn observe(&mut self, event: &TickEvent, _cpu: &Cpu) -> io::Result<()> {
+        self.ticks += 1;
+        if let Some(instruction) = event.completed {
+            self.complete(instruction);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, last: Option<ExecutedInstruction>, _cpu: &Cpu) -> io::Result<()> {
+        if let Some(instruction) = last {
+            self.complete(instruction);
+        }
+
+        Ok(())
+    }
+}
+
+impl Profile {
+    pub fn to_text(&self) -> String {
+        let mut output = String::new();
+        let share = |cost: &Cost| 100.0 * cost.ticks as f64 / self.total.ticks.max(1) as f64;
+
+        let _ = writeln!(
+            output,
+            "Profile: {} instructions, {} ticks, TPI {:.2}",
+            self.total.instructions,
+            self.total.ticks,
+            self.total.ticks_per_instruction()
+        );
+
+        output.push('\n');
+        let _ = writeln!(output, "Hottest instructions:");
+        let _ = writeln!(
+            output,
+            "{:<8}{:<24}{:<24}{:>10}{:>10}{:>8}",
+            "address", "label", "instruction", "count", "ticks", "%"
+        );
+        for instruction in self.instructions.iter().take(TOP) {
+            let _ = writeln!(
+                output,
+                "{:<8}{:<24}{:<24}{:>10}{:>10}{:>8.2}",
+                format!("{:#06x}", instruction.address),
+                instruction
+                    .label
+                    .as_deref()
+                    .map(|label| format_label((label, instruction.offset)))
+                    .unwrap_or_default(),
+                instruction.instruction,
+                instruction.cost.instructions,
+                instruction.cost.ticks,
+                share(&instruction.cost)
+            );
+        }
+
+        output.push('\n');
+        let _ = writeln!(output, "Hottest loops:");
+        let _ = writeln!(
+            output,
+            "{:<16}{:<24}{:>12}{:>10}{:>8}",
+            "addresses", "label", "iterations", "ticks", "%"
+        );
+        for profile in self.loops.iter().take(TOP) {
+            let _ = writeln!(
+                output,
+                "{:<16}{:<24}{:>12}{:>10}{:>8.2}",
+                format!("{:#06x}..={:#06x}", profile.start, profile.end),
+                profile.label.as_deref().unwrap_or_default(),
+                profile.iterations,
+                profile.cost.ticks,
+                share(&profile.cost)
+            );
+        }
+
+        for (title, table) in [
+            ("label", &self.labels),
+            ("opcode", &self.opcodes),
+            ("operand type", &self.operand_types),
+        ] {
+            output.push('\n');
+            let _ = writeln!(
+                output,
+                "{:<24}{:>14}{:>10}{:>8}{:>8}",
+                title, "instructions", "ticks", "TPI", "%"
+            );
+            for NamedCost { name, cost } in table.iter().take(TOP) {
+                let _ = writeln!(
+                    output,
+                    "{:<24}{:>14}{:>10}{:>8.2}{:>8.2}",
+                    name,
+                    cost.instructions,
+                    cost.ticks,
+                    cost.ticks_per_instruction(),
+                    share(cost)
+                );
+            }
+        }
+
+        output
+    }
+
+    /// Folded stacks for flamegraph tools: `program;label;instruction ticks`
+    pub fn to_folded(&self) -> String {
+        let mut output = String::new();
+        let mut instructions: Vec<&InstructionProfile> = self.instructions.iter().collect();
+        instructions.sort_by_key(|instruction| instruction.address);
+
+        for instruction in instructions {
+            let label = instruction.label.as_deref().unwrap_or("<none>");
+            let _ = writeln!(
+                output,
+                "program;{label};{:#06x} {} {}",
+                instruction.address, instruction.instruction, instruction.cost.ticks
+            );
+        }
+
+        output
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn format_label((label, offset): (&str, RawAddress)) -> String {
+    match offset {
+        0 => label.to_owned(),
+        offset => format!("{label}+{offset}"),
+    }
+}
+
+/// Hottest first
+fn sorted(costs: BTreeMap<String, Cost>) -> Vec<NamedCost> {
+    let mut costs: Vec<NamedCost> = costs
+        .into_iter()
+        .map(|(name, cost)| NamedCost { name, cost })
+        .collect();
+    costs.sort_by_key(|named| std::cmp::Reverse(named.cost.ticks));
+
+    costs
+}
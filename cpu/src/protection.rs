@@ -0,0 +1,131 @@
+//! Memory protection. Every cell belongs to code, data or read-only data
+//! region, which decides whether CPU may read, write or execute it.
+//! Kinds come from sections of program: marked by `org` or told by content.
+//! Low memory may be closed for reads and writes to catch null pointers
+
+use std::fmt::Display;
+
+use isa::{CompiledProgram, MemoryItem, RawAddress, SectionKind};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// instruction fetch
+    Execute,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtectionRegion {
+    pub start: RawAddress,
+    /// inclusive
+    pub end: RawAddress,
+    pub kind: SectionKind,
+}
+
+/// Access was refused
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// region does not permit access
+    Region { access: Access, kind: SectionKind },
+    /// data access below `low_memory`
+    LowMemory { access: Access },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::Region { access, kind } => write!(f, "{access} of {kind} is not permitted"),
+            Violation::LowMemory { access } => write!(f, "{access} of protected low memory"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryProtection {
+    /// memory outside of every region is data
+    pub regions: Vec<ProtectionRegion>,
+    /// cells from zero which may be neither read nor written.
+    /// Instructions there are still fetched
+    pub low_memory: usize,
+}
+
+impl ProtectionRegion {
+    pub fn contains(&self, address: RawAddress) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+impl MemoryProtection {
+    /// Regions of program sections. Section without kind is split into
+    /// code of its instructions and data of its words
+    pub fn new(program: &CompiledProgram, low_memory: usize) -> Self {
+        let mut regions: Vec<ProtectionRegion> = Vec::new();
+
+        for section in &program.sections {
+            for (offset, item) in section.items.iter().enumerate() {
+                let address = section.start_address.wrapping_add(offset as RawAddress);
+                let kind = section.kind.unwrap_or(match item {
+                    MemoryItem::Command(_) => SectionKind::Code,
+                    MemoryItem::Data(_) => SectionKind::Data,
+                });
+
+                match regions.last_mut() {
+                    Some(region)
+                        if region.kind == kind && region.end.checked_add(1) == Some(address) =>
+                    {
+                        region.end = address;
+                    }
+                    _ => regions.push(ProtectionRegion {
+                        start: address,
+                        end: address,
+                        kind,
+                    }),
+                }
+            }
+        }
+
+        Self {
+            regions,
+            low_memory,
+        }
+    }
+
+    pub fn kind_of(&self, address: RawAddress) -> SectionKind {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.contains(address))
+            .map_or(SectionKind::Data, |region| region.kind)
+    }
+
+    pub fn check(&self, address: RawAddress, access: Access) -> Result<(), Violation> {
+        if access != Access::Execute && (address as usize) < self.low_memory {
+            return Err(Violation::LowMemory { access });
+        }
+
+        let kind = self.kind_of(address);
+        let permitted = match kind {
+            SectionKind::Code => access != Access::Write,
+            SectionKind::Data => access != Access::Execute,
+            SectionKind::Rodata => access == Access::Read,
+        };
+
+        if !permitted {
+            return Err(Violation::Region { access, kind });
+        }
+
+        Ok(())
+    }
+}
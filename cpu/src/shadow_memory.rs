@@ -0,0 +1,235 @@
+//! Shadow memory checker. Memory starts zeroed, so forgotten variable
+//! silently reads as zero. Checker tracks what every cell holds:
+//! nothing yet, instruction or data, and warns on suspicious accesses
+
+use std::{collections::BTreeMap, fmt::Display, io};
+
+use isa::{CompiledProgram, DebugInfo, MemoryItem, Opcode, RawAddress, MEMORY_SIZE};
+use serde::Serialize;
+
+use crate::{
+    cpu::{Cpu, ExecutedInstruction},
+    observer::{TickEvent, TickObserver},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    /// neither loaded with program nor written since
+    Uninitialized,
+    Instruction,
+    Data,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    /// instruction used value of cell nobody initialized
+    UninitializedRead,
+    /// cell holding instruction was overwritten
+    InstructionOverwritten,
+    /// instruction was fetched from cell holding data or nothing
+    DataFetched,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MemoryWarning {
+    pub kind: WarningKind,
+    /// accessed cell
+    pub address: RawAddress,
+    /// instruction which accessed it
+    pub program_counter: RawAddress,
+    /// when it happened first
+    pub tick: usize,
+    /// how many times it happened
+    pub count: usize,
+    /// nearest labels of cell and instruction
+    pub address_label: Option<String>,
+    pub program_counter_label: Option<String>,
+}
+
+impl Display for MemoryWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = |address: RawAddress, label: &Option<String>| match label {
+            Some(label) => format!("{address:#06x} <{label}>"),
+            None => format!("{address:#06x}"),
+        };
+        let cell = location(self.address, &self.address_label);
+        let instruction = location(self.program_counter, &self.program_counter_label);
+
+        match self.kind {
+            WarningKind::UninitializedRead => write!(
+                f,
+                "read of uninitialized {cell} by instruction at {instruction}"
+            )?,
+            WarningKind::InstructionOverwritten => write!(
+                f,
+                "write over instruction at {cell} by instruction at {instruction}"
+            )?,
+            WarningKind::DataFetched => write!(f, "instruction fetch from data at {cell}")?,
+        }
+
+        write!(f, ", first at tick {}", self.tick)?;
+        if self.count > 1 {
+            write!(f, ", {} times", self.count)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ShadowMemory {
+    cells: Vec<Cell>,
+    debug_info: DebugInfo,
+    // reads of instruction in progress, fetch excluded
+    reads: Vec<(RawAddress, usize)>,
+    // the same access by the same instruction is reported once
+    warnings: BTreeMap<(WarningKind, RawAddress, RawAddress), MemoryWarning>,
+}
+
+impl ShadowMemory {
+    /// Cells of program sections are initialized, the rest are not.
+    /// Labels of warnings come from debug info
+    pub fn new(program: &CompiledProgram, debug_info: DebugInfo) -> Self {
+        let mut cells = vec![Cell::Uninitialized; MEMORY_SIZE];
+        for section in &program.sections {
+            let start = section.start_address as usize;
+            for (cell, item) in cells[start..].iter_mut().zip(&section.items) {
+                *cell = kind_of(*item);
+            }
+        }
+
+        Self {
+            cells,
+            debug_info,
+            reads: Vec::new(),
+            warnings: BTreeMap::new(),
+        }
+    }
+
+    /// In order of first occurrence
+    pub fn warnings(&self) -> Vec<MemoryWarning> {
+        let mut warnings: Vec<MemoryWarning> = self.warnings.values().cloned().collect();
+        warnings.sort_by_key(|warning| warning.tick);
+
+        warnings
+    }
+
+    fn warn(
+        &mut self,
+        kind: WarningKind,
+        address: RawAddress,
+        program_counter: RawAddress,
+        tick: usize,
+    ) {
+        let label = |address| {
+            self.debug_info
+                .nearest_label(address)
+                .map(|(label, offset)| match offset {
+                    0 => label.to_owned(),
+                    offset => format!("{label}+{offset}"),
+                })
+        };
+        let address_label = label(address);
+        let program_counter_label = label(program_counter);
+
+        self.warnings
+            .entry((kind, address, program_counter))
+            .and_modify(|warning| warning.count += 1)
+            .or_insert_with(|| {
+                let warning = MemoryWarning {
+                    kind,
+                    address,
+                    program_counter,
+                    tick,
+                    count: 1,
+                    address_label,
+                    program_counter_label,
+                };
+                log::warn!("Memory check: {warning}");
+                warning
+            });
+    }
+
+    /// Instruction completed: its reads are checked. Operand routine
+    /// reads target of STORE and jumps too, but that value is not used
+    fn check_reads(&mut self, instruction: ExecutedInstruction) {
+        let reads = std::mem::take(&mut self.reads);
+
+        let ignores_target = matches!(
+            instruction.command.opcode,
+            Opcode::STORE | Opcode::JUMP | Opcode::JZC | Opcode::JZS | Opcode::JCC | Opcode::JCS
+        );
+        let used = match reads.len() {
+            0 => 0,
+            all if ignores_target => all - 1,
+            all => all,
+        };
+
+        for &(address, tick) in &reads[..used] {
+            if self.cells[address as usize] == Cell::Uninitialized {
+                self.warn(
+                    WarningKind::UninitializedRead,
+                    address,
+                    instruction.address,
+                    tick,
+                );
+            }
+        }
+    }
+}
+
+impl TickObserver for ShadowMemory {
+    fn observe(&mut self, event: &TickEvent, cpu: &Cpu) -> io::Result<()> {
+        for access in &event.memory {
+            let cell = access.address as usize;
+
+            if access.write {
+                if self.cells[cell] == Cell::Instruction {
+                    let program_counter = cpu
+                        .last_instruction()
+                        .map_or(event.registers.program_counter, |instruction| {
+                            instruction.address
+                        });
+                    self.warn(
+                        WarningKind::InstructionOverwritten,
+                        access.address,
+                        program_counter,
+                        event.tick,
+                    );
+                }
+                self.cells[cell] = kind_of(access.value);
+            } else if event.fetching {
+                if self.cells[cell] != Cell::Instruction {
+                    self.warn(
+                        WarningKind::DataFetched,
+                        access.address,
+                        access.address,
+                        event.tick,
+                    );
+                }
+            } else {
+                self.reads.push((access.address, event.tick));
+            }
+        }
+
+        if let Some(instruction) = event.completed {
+            self.check_reads(instruction);
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, last: Option<ExecutedInstruction>, _cpu: &Cpu) -> io::Result<()> {
+        if let Some(instruction) = last {
+            self.check_reads(instruction);
+        }
+
+        Ok(())
+    }
+}
+
+fn kind_of(item: MemoryItem) -> Cell {
+    match item {
+        MemoryItem::Data(_) => Cell::Data,
+        MemoryItem::Command(_) => Cell::Instruction,
+    }
+}
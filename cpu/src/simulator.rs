@@ -0,0 +1,213 @@
+//! Step-wise control over CPU for tests, debuggers and other front ends.
+//! Simulator never runs on its own: every call advances the machine
+//! by a bounded amount and returns control to the caller.
+
+use isa::{CompiledProgram, RawAddress, RawPort};
+
+use crate::{
+    cpu::{
+        Cpu, ExecutionCounters, HaltReason, History, HistoryConfig, Registers, RunReport, Status,
+    },
+    io_controller::Device,
+    memory::{Memory, MemoryError},
+};
+
+#[derive(Debug)]
+pub struct Simulator {
+    cpu: Cpu,
+    history: Option<History>,
+}
+
+impl From<Cpu> for Simulator {
+    fn from(cpu: Cpu) -> Self {
+        Self::new(cpu)
+    }
+}
+
+impl Simulator {
+    pub fn new(cpu: Cpu) -> Self {
+        Self { cpu, history: None }
+    }
+
+    /// Record ticks from now on, so they can be stepped back.
+    /// Changes made through accessors are not recorded
+    pub fn with_history(mut self, config: HistoryConfig) -> Self {
+        self.history = Some(History::new(&self.cpu, config));
+
+        self
+    }
+
+    /// Executes single microinstruction. Returns `false` when CPU stops
+    pub fn step_tick(&mut self) -> bool {
+        match &mut self.history {
+            Some(history) => history.tick(&mut self.cpu),
+            None => self.cpu.tick(),
+        }
+    }
+
+    /// Executes ticks until next instruction fetch begins.
+    /// Returns `false` when CPU stops
+    pub fn step_instruction(&mut self) -> bool {
+        loop {
+            if !self.step_tick() {
+                return false;
+            }
+
+            if self.cpu.control_unit().is_fetching_instruction() {
+                return true;
+            }
+        }
+    }
+
+    /// Ticks until `predicate` holds for CPU state after a tick.
+    /// Returns `false` when CPU stops first
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&Cpu) -> bool) -> bool {
+        loop {
+            if !self.step_tick() {
+                return false;
+            }
+
+            if predicate(&self.cpu) {
+                return true;
+            }
+        }
+    }
+
+    /// Runs until CPU stops
+    pub fn run(&mut self) -> RunReport {
+        while self.step_tick() {}
+
+        self.cpu.finish()
+    }
+
+    /// Pulls reset line, see `Cpu::reset`. History starts over
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.restart_history();
+    }
+
+    /// Resets and burns program into memory again, so each run starts
+    /// from the same image
+    pub fn reset_with_program(&mut self, program: CompiledProgram) -> Result<(), MemoryError> {
+        self.cpu.reset_with_program(program)?;
+        self.restart_history();
+
+        Ok(())
+    }
+
+    /// Replaces device at port, e.g. with one holding next input.
+    /// Returns previously connected device
+    pub fn connect(&mut self, port: RawPort, device: Box<dyn Device>) -> Option<Box<dyn Device>> {
+        let previous = self.cpu.io_controller_mut().reconnect(port, device);
+        self.restart_history();
+
+        previous
+    }
+
+    fn restart_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            *history = History::new(&self.cpu, history.config());
+        }
+    }
+
+    /// Undoes last tick. Returns `false` when history is disabled
+    /// or does not reach further back
+    pub fn step_back_tick(&mut self) -> bool {
+        self.history
+            .as_mut()
+            .and_then(|history| history.step_back(&mut self.cpu))
+            .is_some()
+    }
+
+    /// Steps back to the start of previous instruction
+    pub fn step_back_instruction(&mut self) -> bool {
+        if !self.step_back_tick() {
+            return false;
+        }
+
+        while !self.cpu.control_unit().is_fetching_instruction() {
+            if !self.step_back_tick() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Steps back to the tick which last wrote memory cell. Stops right
+    /// before that tick, so the next tick performs the write.
+    /// Returns `false` when no write is found within history,
+    /// CPU is then left where it was
+    pub fn run_back_to_write(&mut self, address: RawAddress) -> bool {
+        let Some(history) = &mut self.history else {
+            return false;
+        };
+
+        let mut undone = 0;
+        while let Some(tick) = history.step_back(&mut self.cpu) {
+            if tick.memory_write == Some(address) {
+                return true;
+            }
+            undone += 1;
+        }
+        history.redo(&mut self.cpu, undone);
+
+        false
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    pub fn halt_reason(&self) -> Option<&HaltReason> {
+        self.cpu.halt_reason()
+    }
+
+    pub fn counters(&self) -> ExecutionCounters {
+        self.cpu.counters()
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.cpu.registers()
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        self.cpu.registers_mut()
+    }
+
+    pub fn status(&self) -> Status {
+        self.cpu.status()
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        self.cpu.set_status(status)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        self.cpu.memory()
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        self.cpu.memory_mut()
+    }
+
+    pub fn device(&self, port: RawPort) -> Option<&dyn Device> {
+        self.cpu.io_controller().device(port)
+    }
+
+    pub fn device_mut(&mut self, port: RawPort) -> Option<&mut (dyn Device + 'static)> {
+        self.cpu.io_controller_mut().device_mut(port)
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> Cpu {
+        self.cpu
+    }
+}
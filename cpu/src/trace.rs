@@ -0,0 +1,222 @@
+//! Machine readable execution trace: JSON record per line, for every tick
+//! or for every instruction. Unlike tick log, fields can be selected
+
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cpu::{Cpu, ExecutedInstruction, Registers, Signal, Status},
+    observer::{IoTransaction, MemoryAccess, TickEvent, TickObserver},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceLevel {
+    #[default]
+    Tick,
+    Instruction,
+}
+
+impl TraceLevel {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level {
+            "tick" => Some(TraceLevel::Tick),
+            "instruction" => Some(TraceLevel::Instruction),
+            _ => None,
+        }
+    }
+}
+
+/// Optional parts of record. Tick and instruction numbers are always present
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceField {
+    /// microprogram counter, tick level only
+    MicroPc,
+    /// issued signals, tick level only
+    Signals,
+    Registers,
+    Status,
+    Memory,
+    Io,
+}
+
+impl TraceField {
+    pub const ALL: [TraceField; 6] = [
+        TraceField::MicroPc,
+        TraceField::Signals,
+        TraceField::Registers,
+        TraceField::Status,
+        TraceField::Memory,
+        TraceField::Io,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceField::MicroPc => "micro_pc",
+            TraceField::Signals => "signals",
+            TraceField::Registers => "registers",
+            TraceField::Status => "status",
+            TraceField::Memory => "memory",
+            TraceField::Io => "io",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        TraceField::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub level: TraceLevel,
+    pub fields: Vec<TraceField>,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            level: TraceLevel::default(),
+            fields: TraceField::ALL.to_vec(),
+        }
+    }
+}
+
+/// Tick, or whole instruction at instruction level.
+/// Registers and status are the ones after it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TraceRecord {
+    /// number of the first tick, counting from 0
+    pub tick: usize,
+    /// number of instruction tick belongs to, counting from 0
+    pub instruction: usize,
+    /// ticks instruction took, instruction level only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ticks: Option<usize>,
+    /// instruction level only. Absent when run stopped during fetch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executed: Option<ExecutedInstruction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub micro_pc: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signals: Option<Vec<String>>,
+    /// tick was spent waiting for memory, microinstruction was not issued
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stall: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registers: Option<Registers>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<MemoryAccess>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub io: Vec<IoTransaction>,
+}
+
+/// Writes trace records into output
+pub struct Tracer<W: Write> {
+    output: W,
+    config: TraceConfig,
+    // instruction in progress at instruction level
+    instruction: Option<TraceRecord>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, config: TraceConfig) -> Self {
+        Self {
+            output,
+            config,
+            instruction: None,
+        }
+    }
+
+    /// Output with records written so far
+    pub fn into_output(self) -> W {
+        self.output
+    }
+
+    fn flush_instruction(
+        &mut self,
+        cpu: &Cpu,
+        executed: Option<ExecutedInstruction>,
+    ) -> io::Result<()> {
+        let Some(mut record) = self.instruction.take() else {
+            return Ok(());
+        };
+        record.ticks = Some(cpu.counters().ticks - record.tick);
+        record.executed = executed;
+
+        self.write(record, cpu)
+    }
+
+    /// Adds state after record and drops fields which are not selected
+    fn write(&mut self, mut record: TraceRecord, cpu: &Cpu) -> io::Result<()> {
+        let selected = |field| self.config.fields.contains(&field);
+
+        record.registers = selected(TraceField::Registers).then(|| cpu.registers().clone());
+        record.status = selected(TraceField::Status).then(|| cpu.status());
+        if !selected(TraceField::MicroPc) {
+            record.micro_pc = None;
+        }
+        if !selected(TraceField::Signals) {
+            record.signals = None;
+        }
+        if !selected(TraceField::Memory) {
+            record.memory.clear();
+        }
+        if !selected(TraceField::Io) {
+            record.io.clear();
+        }
+
+        serde_json::to_writer(&mut self.output, &record)?;
+        writeln!(self.output)
+    }
+}
+
+impl<W: Write> TickObserver for Tracer<W> {
+    fn observe(&mut self, event: &TickEvent, cpu: &Cpu) -> io::Result<()> {
+        match self.config.level {
+            TraceLevel::Tick => {
+                let signals = Signal::ALL
+                    .iter()
+                    .filter(|signal| event.micro_instruction.contains(signal))
+                    .map(|signal| format!("{signal:?}"))
+                    .collect();
+                let record = TraceRecord {
+                    tick: event.tick,
+                    instruction: event.instruction,
+                    micro_pc: event.micro_pc,
+                    signals: Some(signals),
+                    stall: event.stall,
+                    memory: event.memory.clone(),
+                    io: event.io.clone(),
+                    ..TraceRecord::default()
+                };
+
+                self.write(record, cpu)
+            }
+            TraceLevel::Instruction => {
+                let record = self.instruction.get_or_insert_with(|| TraceRecord {
+                    tick: event.tick,
+                    instruction: event.instruction,
+                    ..TraceRecord::default()
+                });
+                record.memory.extend_from_slice(&event.memory);
+                record.io.extend_from_slice(&event.io);
+
+                if event.completed.is_some() {
+                    self.flush_instruction(cpu, event.completed)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self, last: Option<ExecutedInstruction>, cpu: &Cpu) -> io::Result<()> {
+        // last record may be cut short
+        self.flush_instruction(cpu, last)?;
+        self.output.flush()
+    }
+}
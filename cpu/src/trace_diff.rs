@@ -0,0 +1,254 @@
+//! Finds the first record where two execution traces diverge. Records are
+//! paired in order and compared field by field, so tick traces of control
+//! units with different timing are better compared at instruction level.
+//! Fields are named as in trace, nested ones with dots: `registers.address`
+
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, BufRead},
+};
+
+use serde_json::Value;
+
+use crate::trace::TraceRecord;
+
+/// Records shown before and after divergence by default
+const CONTEXT: usize = 3;
+
+#[derive(Debug)]
+pub enum TraceDiffError {
+    Read(io::Error),
+    InvalidRecord { line: usize, message: String },
+}
+
+impl Error for TraceDiffError {}
+
+impl Display for TraceDiffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceDiffError::Read(error) => writeln!(f, "Could not read trace: {error}"),
+            TraceDiffError::InvalidRecord { line, message } => {
+                writeln!(f, "Invalid trace record on line {line}: {message}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for TraceDiffError {
+    fn from(value: io::Error) -> Self {
+        Self::Read(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDifference {
+    pub field: String,
+    /// `null` when field is absent
+    pub left: Value,
+    pub right: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceDivergence {
+    /// counting from 0
+    pub record: usize,
+    /// `None` when trace ended before
+    pub left: Option<TraceRecord>,
+    pub right: Option<TraceRecord>,
+    /// empty when one of traces ended
+    pub differences: Vec<FieldDifference>,
+    /// records around divergence, the diverging one included
+    pub left_context: Vec<TraceRecord>,
+    pub right_context: Vec<TraceRecord>,
+    /// index of the first record of context
+    pub context_start: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceDiff {
+    ignored: Vec<String>,
+    context: usize,
+}
+
+impl Default for TraceDiff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceDiff {
+    pub fn new() -> Self {
+        Self {
+            ignored: Vec::new(),
+            context: CONTEXT,
+        }
+    }
+
+    /// Ignoring field ignores everything nested into it
+    pub fn with_ignored(mut self, fields: Vec<String>) -> Self {
+        self.ignored = fields;
+
+        self
+    }
+
+    /// Records shown before and after divergence
+    pub fn with_context(mut self, context: usize) -> Self {
+        self.context = context;
+
+        self
+    }
+
+    /// `None` when traces agree
+    pub fn first_divergence(
+        &self,
+        left: &[TraceRecord],
+        right: &[TraceRecord],
+    ) -> Option<TraceDivergence> {
+        let record = (0..left.len().max(right.len())).find(|&index| {
+            match (left.get(index), right.get(index)) {
+                (Some(left), Some(right)) => !self.differences(left, right).is_empty(),
+                _ => true,
+            }
+        })?;
+
+        let context_start = record.saturating_sub(self.context);
+        let context = |trace: &[TraceRecord]| {
+            let end = (record + self.context + 1).min(trace.len());
+            trace.get(context_start..end).unwrap_or_default().to_vec()
+        };
+
+        let (left_record, right_record) = (left.get(record), right.get(record));
+        let differences = match (left_record, right_record) {
+            (Some(left), Some(right)) => self.differences(left, right),
+            _ => Vec::new(),
+        };
+
+        Some(TraceDivergence {
+            record,
+            left: left_record.cloned(),
+            right: right_record.cloned(),
+            differences,
+            left_context: context(left),
+            right_context: context(right),
+            context_start,
+        })
+    }
+
+    fn differences(&self, left: &TraceRecord, right: &TraceRecord) -> Vec<FieldDifference> {
+        let mut differences = Vec::new();
+        self.compare("", &to_value(left), &to_value(right), &mut differences);
+
+        differences
+    }
+
+    /// Objects are compared field by field, anything else as a whole
+    fn compare(
+        &self,
+        field: &str,
+        left: &Value,
+        right: &Value,
+        differences: &mut Vec<FieldDifference>,
+    ) {
+        if self.is_ignored(field) {
+            return;
+        }
+
+        match (left, right) {
+            (Value::Object(left), Value::Object(right)) => {
+                let mut keys: Vec<&String> = left.keys().chain(right.keys()).collect();
+                keys.sort();
+                keys.dedup();
+
+                for key in keys {
+                    let nested = match field {
+                        "" => key.clone(),
+                        field => format!("{field}.{key}"),
+                    };
+                    self.compare(
+                        &nested,
+                        left.get(key).unwrap_or(&Value::Null),
+                        right.get(key).unwrap_or(&Value::Null),
+                        differences,
+                    );
+                }
+            }
+            (left, right) if left != right => differences.push(FieldDifference {
+                field: field.to_owned(),
+                left: left.clone(),
+                right: right.clone(),
+            }),
+            _ => (),
+        }
+    }
+
+    fn is_ignored(&self, field: &str) -> bool {
+        self.ignored.iter().any(|ignored| {
+            field == ignored
+                || field
+                    .strip_prefix(ignored.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+}
+
+/// Records of JSON Lines trace. Empty lines are skipped
+pub fn read_trace(input: impl BufRead) -> Result<Vec<TraceRecord>, TraceDiffError> {
+    let mut records = Vec::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record =
+            serde_json::from_str(&line).map_err(|error| TraceDiffError::InvalidRecord {
+                line: index + 1,
+                message: error.to_string(),
+            })?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+impl Display for TraceDivergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let position = |record: &Option<TraceRecord>| match record {
+            Some(record) => format!("tick {}, instruction {}", record.tick, record.instruction),
+            None => "trace ended".to_owned(),
+        };
+
+        writeln!(f, "Traces diverge at record {}", self.record)?;
+        writeln!(f, "  left:  {}", position(&self.left))?;
+        writeln!(f, "  right: {}", position(&self.right))?;
+
+        if !self.differences.is_empty() {
+            writeln!(f, "Differences:")?;
+            for FieldDifference { field, left, right } in &self.differences {
+                writeln!(f, "  {field}: {left} != {right}")?;
+            }
+        }
+
+        for (side, context) in [("Left", &self.left_context), ("Right", &self.right_context)] {
+            writeln!(f, "{side}:")?;
+            for (offset, record) in context.iter().enumerate() {
+                let marker = if self.context_start + offset == self.record {
+                    '>'
+                } else {
+                    ' '
+                };
+                // as written in trace
+                let record = serde_json::to_string(record).map_err(|_| std::fmt::Error)?;
+                writeln!(f, "{marker} {record}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn to_value(record: &TraceRecord) -> Value {
+    serde_json::to_value(record).expect("trace record is plain data")
+}
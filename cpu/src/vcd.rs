@@ -0,0 +1,186 @@
+//! Value Change Dump of data path and control signals for waveform
+//! viewers like GTKWave. One timestep is one tick: values at `#n` are
+//! registers during tick `n` and signals issued on it
+
+use std::io::{self, Write};
+
+use isa::CompiledCommand;
+
+use crate::{
+    cpu::{Cpu, ExecutedInstruction, Microinstruction, Registers, Signal, Status},
+    observer::{TickEvent, TickObserver},
+};
+
+/// Name and width in bits of data path variables, in order of `VcdWriter::values`
+const DATA_PATH: [(&str, usize); 11] = [
+    ("acc", 32),
+    // instructions are encoded by `MemoryItem::encode`
+    ("dr", 32),
+    ("ar", 16),
+    ("pc", 16),
+    // `Opcode::code`
+    ("cr_opcode", 8),
+    // `OperandType::code`
+    ("cr_operand_type", 8),
+    ("cr_operand", 16),
+    ("z", 1),
+    ("c", 1),
+    // undefined for hardwired control unit
+    ("micro_pc", 16),
+    // tick is spent waiting for memory
+    ("stall", 1),
+];
+
+pub struct VcdWriter<W: Write> {
+    output: W,
+    // values dumped last, `None` before the first timestep
+    dumped: Option<Vec<String>>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            dumped: None,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        writeln!(
+            self.output,
+            "$version cpu {} $end",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(self.output, "$timescale 1ns $end")?;
+        writeln!(self.output, "$scope module cpu $end")?;
+
+        for (index, (name, width)) in DATA_PATH.iter().enumerate() {
+            writeln!(
+                self.output,
+                "$var wire {width} {} {name} $end",
+                identifier(index)
+            )?;
+        }
+
+        writeln!(self.output, "$scope module signals $end")?;
+        for (index, signal) in Signal::ALL.iter().enumerate() {
+            writeln!(
+                self.output,
+                "$var wire 1 {} {signal:?} $end",
+                identifier(DATA_PATH.len() + index)
+            )?;
+        }
+        writeln!(self.output, "$upscope $end")?;
+        writeln!(self.output, "$upscope $end")?;
+        writeln!(self.output, "$enddefinitions $end")
+    }
+
+    /// Writes values which changed since previous timestep
+    fn dump(&mut self, time: usize, values: Vec<String>) -> io::Result<()> {
+        let first = self.dumped.is_none();
+        if first {
+            self.write_header()?;
+        }
+
+        writeln!(self.output, "#{time}")?;
+        if first {
+            writeln!(self.output, "$dumpvars")?;
+        }
+        for (index, value) in values.iter().enumerate() {
+            let changed = self
+                .dumped
+                .as_ref()
+                .is_none_or(|dumped| dumped[index] != *value);
+            if !changed {
+                continue;
+            }
+
+            // scalar value is followed by identifier immediately
+            let separator = if value.starts_with('b') { " " } else { "" };
+            writeln!(self.output, "{value}{separator}{}", identifier(index))?;
+        }
+        if first {
+            writeln!(self.output, "$end")?;
+        }
+
+        self.dumped = Some(values);
+
+        Ok(())
+    }
+}
+
+impl<W: Write> TickObserver for VcdWriter<W> {
+    fn observe(&mut self, event: &TickEvent, _cpu: &Cpu) -> io::Result<()> {
+        let values = values(
+            &event.registers,
+            event.status,
+            event.micro_pc,
+            &event.micro_instruction,
+            event.stall,
+        );
+
+        self.dump(event.tick, values)
+    }
+
+    /// Final state, with no signals issued
+    fn finish(&mut self, _last: Option<ExecutedInstruction>, cpu: &Cpu) -> io::Result<()> {
+        let values = values(
+            cpu.registers(),
+            cpu.status(),
+            cpu.control_unit().microcode_program_counter(),
+            &Microinstruction::new(),
+            false,
+        );
+        self.dump(cpu.counters().ticks, values)?;
+
+        self.output.flush()
+    }
+}
+
+fn values(
+    registers: &Registers,
+    status: Status,
+    micro_pc: Option<usize>,
+    micro_instruction: &Microinstruction,
+    stall: bool,
+) -> Vec<String> {
+    let vector = |value: u32| format!("b{value:b}");
+    let scalar = |value: bool| if value { "1" } else { "0" }.to_owned();
+    let CompiledCommand { opcode, operand } = registers.command;
+
+    let mut values = vec![
+        vector(registers.accumulator),
+        vector(registers.data.encode()),
+        vector(registers.address as u32),
+        vector(registers.program_counter as u32),
+        vector(opcode.code() as u32),
+        vector(operand.operand_type.code() as u32),
+        vector(operand.operand as u32),
+        scalar(status.zero),
+        scalar(status.carry),
+        micro_pc.map_or_else(|| "bx".to_owned(), |micro_pc| vector(micro_pc as u32)),
+        scalar(stall),
+    ];
+    values.extend(
+        Signal::ALL
+            .iter()
+            .map(|signal| scalar(micro_instruction.contains(signal))),
+    );
+
+    values
+}
+
+/// Short printable identifier of variable
+fn identifier(mut index: usize) -> String {
+    const FIRST: u8 = b'!';
+    const BASE: usize = (b'~' - FIRST + 1) as usize;
+
+    let mut identifier = String::new();
+    loop {
+        identifier.push((FIRST + (index % BASE) as u8) as char);
+        index /= BASE;
+        if index == 0 {
+            return identifier;
+        }
+    }
+}
@@ -0,0 +1,142 @@
+import json
+import os
+import subprocess
+
+import pytest
+
+SOURCE = os.path.abspath("programs/hello_world.asm")
+# hello_world.asm: `loop:` label is on line 23, its first instruction on line 24
+LOOP_LABEL_LINE = 23
+LOOP_LINE = 24
+
+
+class Client:
+    """Minimal Debug Adapter Protocol client"""
+
+    def __init__(self, adapter):
+        self.adapter = adapter
+        self.sequence = 0
+        self.events = []
+
+    def send(self, command, arguments=None):
+        self.sequence += 1
+        message = {"seq": self.sequence, "type": "request", "command": command}
+        if arguments is not None:
+            message["arguments"] = arguments
+
+        content = json.dumps(message).encode()
+        self.adapter.stdin.write(b"Content-Length: %d\r\n\r\n" % len(content) + content)
+        self.adapter.stdin.flush()
+        return self.sequence
+
+    def receive(self):
+        length = None
+        while True:
+            header = self.adapter.stdout.readline().decode().strip()
+            if not header and length is not None:
+                break
+            if header.startswith("Content-Length:"):
+                length = int(header.split(":")[1])
+        return json.loads(self.adapter.stdout.read(length))
+
+    def request(self, command, arguments=None):
+        """Response body, events which arrive meanwhile are kept"""
+        sequence = self.send(command, arguments)
+        while True:
+            message = self.receive()
+            if message["type"] == "event":
+                self.events.append(message)
+            elif message["request_seq"] == sequence:
+                assert message["success"], message.get("message")
+                return message.get("body")
+
+    def event(self, name):
+        """Body of the next event with given name"""
+        while True:
+            while self.events:
+                event = self.events.pop(0)
+                if event["event"] == name:
+                    return event.get("body")
+            self.events.append(self.receive())
+
+    def frame(self):
+        return self.request("stackTrace", {"threadId": 1})["stackFrames"][0]
+
+    def evaluate(self, expression):
+        return self.request("evaluate", {"expression": expression})["result"]
+
+    def breakpoints(self, lines):
+        arguments = {
+            "source": {"path": SOURCE},
+            "breakpoints": [{"line": line} for line in lines],
+        }
+        return self.request("setBreakpoints", arguments)["breakpoints"]
+
+
+@pytest.fixture(scope="module")
+def build():
+    # adapter runs assembler lying next to cpu executable
+    os.system("cd assembler && cargo build")
+    os.system("cd cpu && cargo build")
+
+
+@pytest.fixture
+def dap(build):
+    adapter = subprocess.Popen(
+        ["cargo", "run", "-q", "--", "dap"],
+        cwd="cpu",
+        stdin=subprocess.PIPE,
+        stdout=subprocess.PIPE,
+    )
+    client = Client(adapter)
+    client.request("initialize", {"adapterID": "cpu"})
+    client.request("launch", {"program": SOURCE})
+    client.event("initialized")
+    yield client
+
+    client.request("disconnect")
+    assert adapter.wait(timeout=10) == 0
+
+
+def test_line_breakpoint(dap):
+    # line without instruction moves to the next one, line past the end is refused
+    breakpoints = dap.breakpoints([LOOP_LABEL_LINE, 100])
+    assert [(breakpoint["verified"], breakpoint["line"]) for breakpoint in breakpoints] == [
+        (True, LOOP_LINE),
+        (False, 100),
+    ]
+
+    dap.request("configurationDone")
+    assert dap.event("stopped")["reason"] == "breakpoint"
+    frame = dap.frame()
+    assert (frame["name"], frame["line"], frame["source"]["path"]) == ("loop", LOOP_LINE, SOURCE)
+    assert dap.evaluate("current_char_ptr") == "1"
+
+    # loop runs once per character
+    dap.request("continue", {"threadId": 1})
+    assert dap.event("stopped")["reason"] == "breakpoint"
+    assert dap.frame()["line"] == LOOP_LINE
+    assert dap.evaluate("current_char_ptr") == "2"
+
+
+def test_step(dap):
+    dap.breakpoints([LOOP_LINE])
+    dap.request("configurationDone")
+    dap.event("stopped")
+
+    dap.request("next", {"threadId": 1})
+    assert dap.event("stopped")["reason"] == "step"
+    assert dap.frame()["line"] == LOOP_LINE + 1
+
+
+def test_exit(dap):
+    dap.breakpoints([LOOP_LINE])
+    dap.request("configurationDone")
+    dap.event("stopped")
+
+    # breakpoints are replaced, none are left
+    dap.breakpoints([])
+    dap.request("continue", {"threadId": 1})
+    assert dap.event("output") == {"category": "stdout", "output": "hello world!"}
+    assert dap.event("exited") == {"exitCode": 0}
+    dap.event("terminated")
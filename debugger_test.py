@@ -0,0 +1,126 @@
+import os
+import re
+import subprocess
+import tempfile
+
+import pytest
+
+PROMPT = "(cpu) "
+
+
+@pytest.fixture(scope="module")
+def fibonachi():
+    with tempfile.TemporaryDirectory() as tmpdirname:
+        program = os.path.join(tmpdirname, "fibonachi.json")
+        debug_info = os.path.join(tmpdirname, "fibonachi.debug.json")
+        source = os.path.abspath("programs/fibonachi.asm")
+
+        os.system(f"cd assembler && cargo run -- {source} {program} --debug-info {debug_info}")
+        yield program, debug_info
+
+
+@pytest.fixture(scope="module")
+def custom_instruction():
+    with tempfile.TemporaryDirectory() as tmpdirname:
+        program = os.path.join(tmpdirname, "custom_instruction.json")
+        debug_info = os.path.join(tmpdirname, "custom_instruction.debug.json")
+        source = os.path.abspath("programs/custom_instruction.asm")
+
+        os.system(f"cd assembler && cargo run -- {source} {program} --debug-info {debug_info}")
+        yield program, debug_info
+
+
+def debug(program, commands):
+    """Outputs of every command, state printing commands are appended"""
+    program, debug_info = program
+    input = os.path.abspath("programs/input.txt")
+    commands = commands + ["registers", "disassemble 0 80", "quit"]
+
+    result = subprocess.run(
+        ["cargo", "run", "-q", "--", "debug", program, input, "--debug-info", debug_info],
+        cwd="cpu",
+        input="\n".join(commands) + "\n",
+        capture_output=True,
+        text=True,
+    )
+    assert result.returncode == 0, result.stderr
+
+    # every command is followed by prompt, except for `quit`
+    return result.stdout.split(PROMPT)[1:-1]
+
+
+def state(outputs):
+    """Registers, counters and memory after all commands"""
+    return outputs[-2:]
+
+
+def cell(outputs, label):
+    """Disassembly of cell at label"""
+    memory = state(outputs)[1].splitlines()
+    return memory[memory.index(f"{label}:") + 1]
+
+
+# history keeps keyframe every 1024 ticks,
+# so some of the steps back replay from keyframe
+@pytest.mark.parametrize("ticks,back", [(50, 20), (700, 699), (1500, 600), (3000, 2000)])
+def test_reverse_tick(fibonachi, ticks, back):
+    stepped_back = debug(fibonachi, [f"tick {ticks}", f"reverse-tick {back}"])
+    fresh = debug(fibonachi, [f"tick {ticks - back}"])
+
+    assert state(stepped_back) == state(fresh)
+
+
+@pytest.mark.parametrize("steps,back", [(10, 3), (300, 250)])
+def test_reverse_step(fibonachi, steps, back):
+    stepped_back = debug(fibonachi, [f"step {steps}", f"reverse-step {back}"])
+    fresh = debug(fibonachi, [f"step {steps - back}"])
+
+    assert state(stepped_back) == state(fresh)
+
+
+def test_reverse_from_halt(fibonachi):
+    stepped_back = debug(fibonachi, ["continue", "reverse-tick 100"])
+    halted = debug(fibonachi, ["continue"])
+    ticks = int(re.search(r"Ticks: (\d+)", state(halted)[0]).group(1))
+    # HALT stops CPU without counting its tick,
+    # the first step back only clears halt
+    fresh = debug(fibonachi, [f"tick {ticks - 99}"])
+
+    assert state(stepped_back) == state(fresh)
+
+
+def test_last_write(fibonachi):
+    outputs = debug(fibonachi, ["continue", "last-write sum"])
+    assert "No write" not in outputs[1]
+
+    # stops right before the tick which writes
+    ticks = int(re.search(r"tick (\d+)", outputs[1]).group(1))
+    fresh = debug(fibonachi, [f"tick {ticks}"])
+    assert state(outputs) == state(fresh)
+
+    # the tick writes final value
+    written = debug(fibonachi, ["continue", "last-write sum", "tick"])
+    halted = debug(fibonachi, ["continue"])
+    assert cell(written, "sum") != cell(outputs, "sum")
+    assert cell(written, "sum") == cell(halted, "sum")
+
+
+def test_start_of_history(fibonachi):
+    outputs = debug(fibonachi, ["tick 10", "reverse-tick 20"])
+    fresh = debug(fibonachi, [])
+
+    assert "Reached start of history" in outputs[1]
+    assert state(outputs) == state(fresh)
+
+
+def test_last_write_not_found(fibonachi):
+    outputs = debug(fibonachi, ["tick 1500", "last-write 0x700"])
+    fresh = debug(fibonachi, ["tick 1500"])
+
+    assert "No write of" in outputs[1]
+    assert state(outputs) == state(fresh)
+
+
+def test_disassemble_user_opcode(custom_instruction):
+    # disassembly is valid assembler syntax
+    assert "user0 two" in state(debug(custom_instruction, []))[1]
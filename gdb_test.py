@@ -0,0 +1,139 @@
+import os
+import socket
+import subprocess
+import tempfile
+
+import pytest
+
+# hello_world.asm: `loop` label is cell 0x14, `current_char_ptr` is cell 0x0e.
+# GDB addresses bytes, cell n lies at 4 * n
+LOOP = 0x50
+CURRENT_CHAR_PTR = 0x38
+
+
+def checksum(payload):
+    return sum(payload.encode()) % 256
+
+
+class Client:
+    """Minimal GDB remote serial protocol client"""
+
+    def __init__(self, port):
+        self.socket = socket.create_connection(("127.0.0.1", port))
+        self.file = self.socket.makefile("rb")
+
+    def command(self, payload):
+        self.socket.sendall(f"${payload}#{checksum(payload):02x}".encode())
+        assert self.file.read(1) == b"+"
+        return self.receive()
+
+    def receive(self):
+        assert self.file.read(1) == b"$"
+        packet = b""
+        while not packet.endswith(b"#"):
+            packet += self.file.read(1)
+        payload = packet[:-1].decode()
+        assert int(self.file.read(2), 16) == checksum(payload)
+        self.socket.sendall(b"+")
+        return payload
+
+    def close(self):
+        self.file.close()
+        self.socket.close()
+
+    def registers(self):
+        """acc, pc, ar, dr, cr, status"""
+        reply = self.command("g")
+        return [int.from_bytes(bytes.fromhex(reply[i : i + 8]), "little") for i in range(0, len(reply), 8)]
+
+    def cell(self, address):
+        return int.from_bytes(bytes.fromhex(self.command(f"m{address:x},4")), "little")
+
+
+def free_port():
+    with socket.socket() as listener:
+        listener.bind(("127.0.0.1", 0))
+        return listener.getsockname()[1]
+
+
+@pytest.fixture(scope="module")
+def program():
+    with tempfile.TemporaryDirectory() as tmpdirname:
+        target = os.path.join(tmpdirname, "hello_world.json")
+        source = os.path.abspath("programs/hello_world.asm")
+
+        os.system(f"cd assembler && cargo run -- {source} {target}")
+        os.system("cd cpu && cargo build")
+        yield target
+
+
+@pytest.fixture
+def gdb(program):
+    port = free_port()
+    input = os.path.abspath("programs/input.txt")
+    stub = subprocess.Popen(
+        ["cargo", "run", "-q", "--", "gdb", program, input, "--port", str(port)],
+        cwd="cpu",
+        stdout=subprocess.DEVNULL,
+        stderr=subprocess.PIPE,
+        text=True,
+    )
+    # stub listens once it says so
+    assert "Waiting for GDB" in stub.stderr.readline()
+
+    client = Client(port)
+    yield client
+
+    # stub stops serving once GDB disconnects or detaches
+    client.close()
+    assert stub.wait(timeout=10) == 0
+
+
+def test_stop_reason_and_registers(gdb):
+    assert gdb.command("?") == "S05"
+    accumulator, program_counter, *_ = gdb.registers()
+    assert (accumulator, program_counter) == (0, 0)
+
+
+def test_read_memory(gdb):
+    # `word 12` of string length and the first letter after `jump start`
+    assert gdb.command("m4,8") == "0c00000068000000"
+    assert gdb.cell(CURRENT_CHAR_PTR) == 1
+
+
+def test_breakpoint(gdb):
+    assert gdb.command(f"Z0,{LOOP:x},4") == "OK"
+    assert gdb.command("c") == "T05swbreak:;"
+    assert gdb.registers()[1] == LOOP
+
+    # loop runs once per character
+    assert gdb.command("c") == "T05swbreak:;"
+    assert gdb.registers()[1] == LOOP
+    assert gdb.cell(CURRENT_CHAR_PTR) == 2
+
+    assert gdb.command(f"z0,{LOOP:x},4") == "OK"
+    assert gdb.command("c") == "W00"
+
+
+def test_step(gdb):
+    # `jump start`
+    assert gdb.command("s") == "S05"
+    assert gdb.registers()[1] == 0x40
+
+    assert gdb.command("s") == "S05"
+    assert gdb.registers()[1] == 0x44
+
+
+def test_watchpoint(gdb):
+    assert gdb.command(f"Z2,{CURRENT_CHAR_PTR:x},4") == "OK"
+    assert gdb.command("c") == f"T05watch:{CURRENT_CHAR_PTR:x};"
+    assert gdb.cell(CURRENT_CHAR_PTR) == 2
+
+
+def test_exit(gdb):
+    assert gdb.command("c") == "W00"
+
+
+def test_detach(gdb):
+    assert gdb.command(f"Z0,{LOOP:x},4") == "OK"
+    assert gdb.command("D") == "OK"
@@ -10,7 +10,7 @@ def test_golden(golden):
         output = os.path.join(tmpdirname, "output.txt")
         target = os.path.join(tmpdirname, "source.json")
         input = os.path.join(tmpdirname, "input.txt")
-        
+
         with open(source, "w", encoding="utf-8") as file:
             file.write(golden["source"])
 
@@ -19,17 +19,64 @@ def test_golden(golden):
 
 
 
-        os.system(f"cd assembler && cargo run -- {source} {target}")
-        with open(target, "r") as file:
-            code = file.read()
-            assert code == golden.out["machine_code"]
-            
+        # empty source: machine code is given as is,
+        # e.g. one assembler can not produce
+        if golden["source"]:
+            os.system(f"cd assembler && cargo run -- {source} {target}")
+            with open(target, "r") as file:
+                code = file.read()
+                assert code == golden.out["machine_code"]
+        else:
+            with open(target, "w", encoding="utf-8") as file:
+                file.write(golden.out["machine_code"])
+
+
+        status = os.system(f"cd cpu && cargo run -- {target} {input} {golden['args']} >> {output}")
+        assert os.waitstatus_to_exitcode(status) == golden.out["exit_code"]
 
-        os.system(f"cd cpu && cargo run -- {target} {input} >> {output}")
         with open(output, "r") as file:
             code = file.read()
             assert code == golden.out["output"]
-    
+
         with open("cpu/cpu.log", "r", encoding="utf-8") as file:
             log = file.read()
             assert log == golden.out["out_log"]
+
+
+
+# several runs of cpu and its subcommands, one after another.
+# They run in temporary directory next to source.json, source.debug.json
+# and input0.txt, input1.txt, ...; `files` are read from there at the end
+@pytest.mark.golden_test("tests/commands/*.yml")
+def test_golden_commands(golden):
+    with tempfile.TemporaryDirectory() as tmpdirname:
+        source = os.path.join(tmpdirname, "source.asm")
+        target = os.path.join(tmpdirname, "source.json")
+        debug_info = os.path.join(tmpdirname, "source.debug.json")
+        output = os.path.join(tmpdirname, "output.txt")
+        manifest = os.path.abspath("cpu/Cargo.toml")
+
+        with open(source, "w", encoding="utf-8") as file:
+            file.write(golden["source"])
+
+        for index, content in enumerate(golden["inputs"]):
+            with open(os.path.join(tmpdirname, f"input{index}.txt"), "w", encoding="utf-8") as file:
+                file.write(content)
+
+        if golden["source"]:
+            os.system(f"cd assembler && cargo run -- {source} {target} --debug-info {debug_info}")
+
+        exit_codes = []
+        for command in golden["commands"]:
+            status = os.system(f"cd {tmpdirname} && cargo run --manifest-path {manifest} -- {command} >> {output}")
+            exit_codes.append(os.waitstatus_to_exitcode(status))
+        assert exit_codes == golden.out["exit_codes"]
+
+        with open(output, "r") as file:
+            assert file.read() == golden.out["output"]
+
+        files = {}
+        for name in golden.out["files"]:
+            with open(os.path.join(tmpdirname, name), "r") as file:
+                files[name] = file.read()
+        assert files == golden.out["files"]
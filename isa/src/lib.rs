@@ -1,5 +1,4 @@
-use core::panic;
-use std::{fmt::Display, mem::size_of};
+use std::{collections::BTreeMap, fmt::Display, mem::size_of};
 
 use serde::{Deserialize, Serialize};
 
@@ -13,7 +12,10 @@ pub type RawOperand = u16;
 pub type RawAddress = RawOperand;
 pub type RawPort = u8;
 
-#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+/// Amount of opcodes which dispatch into writable control store
+pub const USER_OPCODES: u8 = 8;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Operand {
     pub operand: RawOperand,
     pub operand_type: OperandType,
@@ -23,7 +25,7 @@ pub struct Operand {
 // label, (label), !label, same with number
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
 pub enum Opcode {
     IN,  // port
     OUT, // port
@@ -46,9 +48,88 @@ pub enum Opcode {
 
     NOP,  // none
     HALT, // none
+
+    // multi-core
+    TAS,    // address. accumulator <- memory, memory <- 1, atomically
+    COREID, // none. accumulator <- id of executing core
+
+    // microcode
+    WCS,      // immediate. writes accumulator into writable control store
+    USER(u8), // any. dispatches into writable control store
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 28] = [
+        Opcode::IN,
+        Opcode::OUT,
+        Opcode::LOAD,
+        Opcode::STORE,
+        Opcode::ADD,
+        Opcode::INC,
+        Opcode::AND,
+        Opcode::CMP,
+        Opcode::SHIFT_LEFT,
+        Opcode::SHIFT_RIGHT,
+        Opcode::JZC,
+        Opcode::JZS,
+        Opcode::JCC,
+        Opcode::JCS,
+        Opcode::JUMP,
+        Opcode::NOP,
+        Opcode::HALT,
+        Opcode::TAS,
+        Opcode::COREID,
+        Opcode::WCS,
+        Opcode::USER(0),
+        Opcode::USER(1),
+        Opcode::USER(2),
+        Opcode::USER(3),
+        Opcode::USER(4),
+        Opcode::USER(5),
+        Opcode::USER(6),
+        Opcode::USER(7),
+    ];
+
+    /// USER index is within writable control store
+    pub fn is_valid(self) -> bool {
+        match self {
+            Opcode::USER(index) => index < USER_OPCODES,
+            _ => true,
+        }
+    }
+
+    /// Index in `ALL`. USER beyond writable control store continues
+    /// numbering after the last one, wrapping around past 255
+    pub fn code(self) -> u8 {
+        const FIRST_USER: u8 = (Opcode::ALL.len() - USER_OPCODES as usize) as u8;
+
+        match self {
+            Opcode::USER(index) => FIRST_USER.wrapping_add(index),
+            opcode => Opcode::ALL
+                .iter()
+                .position(|&other| other == opcode)
+                .expect("every opcode but USER is listed in Opcode::ALL")
+                as u8,
+        }
+    }
+
+    /// Opcodes beyond `ALL` are not decoded
+    pub fn from_code(code: u8) -> Option<Self> {
+        Opcode::ALL.get(code as usize).copied()
+    }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+/// Mnemonic as written in assembly, e.g. `LOAD` or `USER0`
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Opcode::USER(index) => write!(f, "USER{index}"),
+            opcode => write!(f, "{opcode:?}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
 pub enum OperandType {
     None,
     Indirect,
@@ -57,13 +138,56 @@ pub enum OperandType {
     Immediate,
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+impl OperandType {
+    pub const ALL: [OperandType; 5] = [
+        OperandType::None,
+        OperandType::Immediate,
+        OperandType::Absolute,
+        OperandType::Relative,
+        OperandType::Indirect,
+    ];
+
+    /// Index in `ALL`
+    pub fn code(self) -> u8 {
+        OperandType::ALL
+            .iter()
+            .position(|&other| other == self)
+            .expect("every operand type is listed in OperandType::ALL") as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        OperandType::ALL.get(code as usize).copied()
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
 pub struct CompiledCommand {
     pub opcode: Opcode,
     #[serde(flatten)]
     pub operand: Operand,
 }
 
+impl CompiledCommand {
+    /// Instructions have no binary form in memory. Debugging front ends
+    /// show them as `operand | operand type << 16 | opcode << 24`
+    pub fn encode(self) -> u32 {
+        (self.opcode.code() as u32) << 24
+            | (self.operand.operand_type.code() as u32) << 16
+            | self.operand.operand as u32
+    }
+
+    /// `None` when opcode or operand type field is out of range
+    pub fn decode(value: u32) -> Option<Self> {
+        Some(CompiledCommand {
+            opcode: Opcode::from_code((value >> 24) as u8)?,
+            operand: Operand {
+                operand: value as RawOperand,
+                operand_type: OperandType::from_code((value >> 16) as u8)?,
+            },
+        })
+    }
+}
+
 impl Display for CompiledCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -74,10 +198,44 @@ impl Display for CompiledCommand {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// What section holds. Decides access CPU with memory protection grants
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    Code,
+    Data,
+    /// read-only data
+    Rodata,
+}
+
+impl SectionKind {
+    /// As written after `org`: `code`, `data` or `rodata`
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind.to_lowercase().as_str() {
+            "code" => Some(Self::Code),
+            "data" => Some(Self::Data),
+            "rodata" => Some(Self::Rodata),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SectionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SectionKind::Code => write!(f, "code"),
+            SectionKind::Data => write!(f, "data"),
+            SectionKind::Rodata => write!(f, "read-only data"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CompiledSection {
     pub start_address: RawAddress,
     pub items: Vec<MemoryItem>,
+    /// `None` when it is up to content of section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<SectionKind>,
 }
 
 impl CompiledSection {
@@ -85,16 +243,66 @@ impl CompiledSection {
         Self {
             start_address: address,
             items: Vec::new(),
+            kind: None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CompiledProgram {
     pub sections: Vec<CompiledSection>,
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+/// What assembler knows about program, but CPU does not need:
+/// names of addresses and source lines of instructions
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DebugInfo {
+    pub labels: BTreeMap<String, RawAddress>,
+    /// counting from 1
+    #[serde(default)]
+    pub lines: BTreeMap<RawAddress, usize>,
+    /// path to source as assembler was given it
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl DebugInfo {
+    pub fn address_of(&self, label: &str) -> Option<RawAddress> {
+        self.labels.get(label).copied()
+    }
+
+    /// Label pointing exactly at address
+    pub fn label_at(&self, address: RawAddress) -> Option<&str> {
+        self.nearest_label(address)
+            .filter(|&(_, offset)| offset == 0)
+            .map(|(label, _)| label)
+    }
+
+    pub fn line_of(&self, address: RawAddress) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// First instruction of `line`, or of the closest line after it
+    /// which has instructions. Returns that line along with address
+    pub fn instruction_at_line(&self, line: usize) -> Option<(usize, RawAddress)> {
+        self.lines
+            .iter()
+            .filter(|&(_, &instruction_line)| instruction_line >= line)
+            .map(|(&address, &instruction_line)| (instruction_line, address))
+            .min()
+    }
+
+    /// Closest label at or before `address` and offset from it
+    pub fn nearest_label(&self, address: RawAddress) -> Option<(&str, RawAddress)> {
+        self.labels
+            .iter()
+            .filter(|&(_, &label_address)| label_address <= address)
+            .max_by_key(|&(_, &label_address)| label_address)
+            .map(|(label, &label_address)| (label.as_str(), address - label_address))
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
 #[serde(untagged)]
 pub enum MemoryItem {
     Data(MemoryDataType),
@@ -111,25 +319,26 @@ impl Display for MemoryItem {
 }
 
 impl MemoryItem {
-    pub fn unwrap_data(self) -> u32 {
+    /// Instructions does not have binary representation
+    pub fn data(self) -> Option<u32> {
         match self {
-            MemoryItem::Data(payload) => payload,
-            MemoryItem::Command(_) => {
-                panic!("Instruction accessed! Instructions does not have binary representation!")
-            }
+            MemoryItem::Data(payload) => Some(payload),
+            MemoryItem::Command(_) => None,
         }
     }
 
-    pub fn unwrap_command(self) -> CompiledCommand {
+    pub fn command(self) -> Option<CompiledCommand> {
         match self {
-            MemoryItem::Data(_) => panic!("Data accessed! Instruction expected!"),
-            MemoryItem::Command(command) => command,
+            MemoryItem::Data(_) => None,
+            MemoryItem::Command(command) => Some(command),
         }
     }
-}
 
-impl From<MemoryItem> for u32 {
-    fn from(value: MemoryItem) -> Self {
-        value.unwrap_data()
+    /// Data as is, instruction encoded by `CompiledCommand::encode`
+    pub fn encode(self) -> u32 {
+        match self {
+            MemoryItem::Data(value) => value,
+            MemoryItem::Command(command) => command.encode(),
+        }
     }
 }
@@ -0,0 +1,22 @@
+jump start
+
+// USER0: subtract operand from accumulator
+// SELECT_RIGHT_DATA, NOT_RIGHT, INC, WRITE_ACCUMULATOR, WRITE_STATUS, SELECT_MC_0
+sub_microcode:
+  word 0x0141_8090
+
+letter:
+  word 0x6a
+
+start:
+  load sub_microcode
+  wcs 0
+
+  load letter
+  // 'j' - 2 = 'h'
+  user0 two
+  out 0
+  halt
+
+two:
+  word 2
@@ -0,0 +1,46 @@
+// run with --cores N
+// cores increment shared counter under spinlock until it reaches target.
+// Each core then prints '<' (60) followed by its id
+
+jump acquire
+
+lock:
+  word 0
+
+counter:
+  word 0
+
+target:
+  word 60
+
+zero:
+  word 0
+
+char_zero:
+  word 0x30
+
+acquire:
+  // Zero flag is set if lock was free
+  tas lock
+  jzc acquire
+
+  load counter
+  cmp target
+  jzs finish
+  inc
+  store counter
+
+  // release
+  load zero
+  store lock
+  jump acquire
+
+finish:
+  out 0
+  coreid
+  add char_zero
+  out 0
+
+  load zero
+  store lock
+  halt